@@ -0,0 +1,321 @@
+//! Compiling the tests, natively and to Wasm, with the compiler of their
+//! language.
+
+use super::wasi_version::WasiVersion;
+use super::wasitests::{WasiOptions, NATIVE_EXTERN_TARGET};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// The `-C opt-level` of the Wasm build of tests without an `opt_level`
+pub const DEFAULT_WASM_OPT_LEVEL: &str = "z";
+
+/// A compiler of tests. The compilations return the compiler's run, a failed
+/// compilation being reported by its status rather than an error.
+pub trait Compiler {
+    /// The name of the compiler of `version`, as shown to the user
+    fn name(&self, version: WasiVersion) -> String;
+
+    /// The first line the compiler of `version`, or the native one if it's
+    /// `None`, prints with `--version`, or `None` if it can't be run
+    fn version(&self, version: Option<WasiVersion>) -> Option<String>;
+
+    /// Compiles `source` to an executable of the host, or of `target`, at
+    /// `output`
+    fn compile_native(
+        &self,
+        source: &Path,
+        output: &Path,
+        target: Option<&str>,
+        options: &WasiOptions,
+        execute_dir: &Path,
+    ) -> io::Result<CompilerRun>;
+
+    /// Compiles `source` to a Wasm module for `version` at `output`
+    fn compile_wasm(
+        &self,
+        source: &Path,
+        output: &Path,
+        version: WasiVersion,
+        options: &WasiOptions,
+        execute_dir: &Path,
+    ) -> io::Result<CompilerRun>;
+}
+
+/// A run of a compiler
+#[derive(Debug)]
+pub struct CompilerRun {
+    /// The command line that ran, as shown to the user
+    pub command: String,
+    pub output: Output,
+}
+
+/// `rustc`, rustup's with the toolchain of each version unless one is given
+#[derive(Debug, Clone, Copy)]
+pub struct Rustc<'a> {
+    pub rustc: Option<&'a Path>,
+}
+
+/// The C compilers: `cc` for the native build and `clang` for Wasm
+#[derive(Debug, Clone, Copy)]
+pub struct Clang<'a> {
+    pub cc: &'a Path,
+    pub clang: &'a Path,
+}
+
+impl Compiler for Rustc<'_> {
+    fn name(&self, version: WasiVersion) -> String {
+        match self.rustc {
+            Some(rustc) => rustc.to_string_lossy().to_string(),
+            None => version.get_compiler_toolchain().to_string(),
+        }
+    }
+
+    fn version(&self, version: Option<WasiVersion>) -> Option<String> {
+        cached_version(rustc_command(version, self.rustc))
+    }
+
+    fn compile_native(
+        &self,
+        source: &Path,
+        output: &Path,
+        target: Option<&str>,
+        options: &WasiOptions,
+        execute_dir: &Path,
+    ) -> io::Result<CompilerRun> {
+        let mut command = rustc_command(None, self.rustc);
+        if let Some(target) = target {
+            command.arg(format!("--target={}", target));
+        }
+        command.args(options.rustc_flags(NATIVE_EXTERN_TARGET, execute_dir));
+        run(command, source, output, &[])
+    }
+
+    fn compile_wasm(
+        &self,
+        source: &Path,
+        output: &Path,
+        version: WasiVersion,
+        options: &WasiOptions,
+        execute_dir: &Path,
+    ) -> io::Result<CompilerRun> {
+        let mut command = rustc_command(Some(version), self.rustc);
+        command.arg(format!("--target={}", target_triple(version, self.rustc)));
+        if options.opt_level.is_none() {
+            command
+                .arg("-C")
+                .arg(format!("opt-level={}", DEFAULT_WASM_OPT_LEVEL));
+        }
+        command.args(options.rustc_flags(version.get_directory_name(), execute_dir));
+        run(command, source, output, &[])
+    }
+}
+
+impl Compiler for Clang<'_> {
+    fn name(&self, _version: WasiVersion) -> String {
+        self.clang.to_string_lossy().to_string()
+    }
+
+    fn version(&self, version: Option<WasiVersion>) -> Option<String> {
+        match version {
+            Some(_) => cached_version(Command::new(self.clang)),
+            None => cached_version(Command::new(self.cc)),
+        }
+    }
+
+    fn compile_native(
+        &self,
+        source: &Path,
+        output: &Path,
+        _target: Option<&str>,
+        options: &WasiOptions,
+        _execute_dir: &Path,
+    ) -> io::Result<CompilerRun> {
+        let mut command = Command::new(self.cc);
+        command.args(options.c_flags());
+        // the linker looks for what the source needs in the libraries after it
+        run(command, source, output, &options.link_args)
+    }
+
+    fn compile_wasm(
+        &self,
+        source: &Path,
+        output: &Path,
+        version: WasiVersion,
+        options: &WasiOptions,
+        _execute_dir: &Path,
+    ) -> io::Result<CompilerRun> {
+        let mut command = Command::new(self.clang);
+        // the versions C tests aren't built for are skipped by `compile`
+        command.arg(format!(
+            "--target={}",
+            version.get_c_target_triple().unwrap()
+        ));
+        if options.opt_level.is_none() {
+            command.arg(format!("-O{}", DEFAULT_WASM_OPT_LEVEL));
+        }
+        command.args(options.c_flags());
+        run(command, source, output, &[])
+    }
+}
+
+/// Runs the compiler `command` on `source`, writing `output`, with the
+/// `link_args` last
+fn run(
+    mut command: Command,
+    source: &Path,
+    output: &Path,
+    link_args: &[String],
+) -> io::Result<CompilerRun> {
+    command.arg(source).arg("-o").arg(output).args(link_args);
+    debug!("Command {:?}", command);
+    Ok(CompilerRun {
+        command: command_line(&command),
+        output: command.output()?,
+    })
+}
+
+/// What the compilers print when they fail for a reason other than the
+/// source, like the machine being busy, so that compiling again may succeed
+const TRANSIENT_FAILURES: &[&str] = &[
+    "Resource temporarily unavailable",
+    "Interrupted system call",
+    "Text file busy",
+    "signal: 9, SIGKILL",
+    "SIGBUS",
+];
+
+/// Runs `compile` up to `attempts` times, again after a short wait while it
+/// fails transiently: the compiler failing with one of the
+/// `TRANSIENT_FAILURES` and no error about the source, or not starting for
+/// lack of resources. The last run is returned.
+pub fn with_retries(
+    attempts: usize,
+    what: &str,
+    compile: impl Fn() -> io::Result<CompilerRun>,
+) -> io::Result<CompilerRun> {
+    let mut attempt = 1;
+    loop {
+        let run = compile();
+        let transient = match &run {
+            Ok(run) if !run.output.status.success() => {
+                let stderr = String::from_utf8_lossy(&run.output.stderr);
+                // `error[E...]`s are about the source, which compiling again won't fix
+                !stderr.contains("error[E")
+                    && TRANSIENT_FAILURES
+                        .iter()
+                        .any(|failure| stderr.contains(failure))
+            }
+            Ok(_) => false,
+            Err(e) => matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+            ),
+        };
+        if !transient || attempt >= attempts {
+            return run;
+        }
+        warn!(
+            "Compiling {} failed for what looks like a transient reason, trying again ({}/{})",
+            what,
+            attempt + 1,
+            attempts
+        );
+        thread::sleep(Duration::from_millis(100 * attempt as u64));
+        attempt += 1;
+    }
+}
+
+/// The `--version`s of the compilers, by command line, as every test asks for
+/// them
+static VERSIONS: Mutex<BTreeMap<String, Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// The first line the compiler of `command` prints with `--version`, run once
+/// per compiler
+fn cached_version(mut command: Command) -> Option<String> {
+    command.arg("--version");
+    let key = command_line(&command);
+    if let Some(version) = VERSIONS.lock().unwrap().get(&key) {
+        return version.clone();
+    }
+    let version = command
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| {
+            String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .next()
+                .map(str::to_string)
+        });
+    VERSIONS.lock().unwrap().insert(key, version.clone());
+    version
+}
+
+/// The command line of `command`, its arguments separated by spaces
+fn command_line(command: &Command) -> String {
+    let mut line = command.get_program().to_string_lossy().to_string();
+    for arg in command.get_args() {
+        line.push(' ');
+        line.push_str(&arg.to_string_lossy());
+    }
+    line
+}
+
+/// The command running the compiler of `version`, or the native one if it's
+/// `None`: rustup's `rustc` with the version's `+toolchain`, or `rustc` if
+/// it's given, as is, as it may not be rustup's
+pub fn rustc_command(version: Option<WasiVersion>, rustc: Option<&Path>) -> Command {
+    match (rustc, version) {
+        (Some(rustc), _) => Command::new(rustc),
+        (None, Some(version)) => {
+            let mut command = Command::new("rustc");
+            command.arg(format!("+{}", version.get_compiler_toolchain()));
+            command
+        }
+        (None, None) => Command::new("rustc"),
+    }
+}
+
+/// The `--print target-list`s of the compilers, by command line, as every
+/// Wasm compilation asks for them
+static TARGET_LISTS: Mutex<BTreeMap<String, Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// The targets the compiler of `command` prints with `--print target-list`,
+/// run once per compiler
+fn cached_target_list(mut command: Command) -> Option<String> {
+    command.arg("--print").arg("target-list");
+    let key = command_line(&command);
+    if let Some(target_list) = TARGET_LISTS.lock().unwrap().get(&key) {
+        return target_list.clone();
+    }
+    let target_list = command
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).to_string());
+    TARGET_LISTS
+        .lock()
+        .unwrap()
+        .insert(key, target_list.clone());
+    target_list
+}
+
+/// The name the compiler of `version` knows its target by, the newest it
+/// recognizes
+fn target_triple(version: WasiVersion, rustc: Option<&Path>) -> &'static str {
+    let triples = version.get_target_triples();
+    let target_list = cached_target_list(rustc_command(Some(version), rustc));
+    match target_list {
+        Some(target_list) => triples
+            .iter()
+            .find(|&&triple| target_list.lines().any(|target| target == triple))
+            .unwrap_or_else(|| triples.last().unwrap()),
+        // the compilation reports what's wrong with the toolchain
+        None => triples.last().unwrap(),
+    }
+}
@@ -0,0 +1,162 @@
+//! A JSON dashboard of what the generated tests validate: the WASI functions
+//! each test imports, what it's expected to do, and how much of the WASI API
+//! the whole suite covers.
+//!
+//! Everything is sorted so the dashboard only changes when the tests do.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use wasmparser::{Parser, Payload};
+
+use super::wasi_version::WasiVersion;
+use super::wasitests::WasiTest;
+
+/// Every function of the WASI API, which is the same in all versions
+pub(crate) const WASI_FUNCTIONS: &[&str] = &[
+    "args_get",
+    "args_sizes_get",
+    "clock_res_get",
+    "clock_time_get",
+    "environ_get",
+    "environ_sizes_get",
+    "fd_advise",
+    "fd_allocate",
+    "fd_close",
+    "fd_datasync",
+    "fd_fdstat_get",
+    "fd_fdstat_set_flags",
+    "fd_fdstat_set_rights",
+    "fd_filestat_get",
+    "fd_filestat_set_size",
+    "fd_filestat_set_times",
+    "fd_pread",
+    "fd_prestat_dir_name",
+    "fd_prestat_get",
+    "fd_pwrite",
+    "fd_read",
+    "fd_readdir",
+    "fd_renumber",
+    "fd_seek",
+    "fd_sync",
+    "fd_tell",
+    "fd_write",
+    "path_create_directory",
+    "path_filestat_get",
+    "path_filestat_set_times",
+    "path_link",
+    "path_open",
+    "path_readlink",
+    "path_remove_directory",
+    "path_rename",
+    "path_symlink",
+    "path_unlink_file",
+    "poll_oneoff",
+    "proc_exit",
+    "proc_raise",
+    "random_get",
+    "sched_yield",
+    "sock_recv",
+    "sock_send",
+    "sock_shutdown",
+];
+
+#[derive(Debug, Serialize)]
+struct Dashboard {
+    wasi_version: String,
+    tests: Vec<TestSummary>,
+    /// The tests exercising each WASI function, by function
+    coverage: BTreeMap<String, Vec<String>>,
+    /// The WASI functions no test exercises
+    uncovered: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TestSummary {
+    name: String,
+    wasi_functions: Vec<String>,
+    expected_result: i64,
+    expected_stdout: String,
+    expected_stderr: String,
+}
+
+/// Returns the sorted names of the WASI functions the Wasm module imports.
+pub(crate) fn wasi_imports(wasm: &[u8]) -> io::Result<Vec<String>> {
+    let mut imports = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Payload::ImportSection(reader) = payload {
+            for import in reader {
+                let import = import.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                if import.module.starts_with("wasi") {
+                    imports.push(import.name.to_string());
+                }
+            }
+        }
+    }
+    imports.sort();
+    imports.dedup();
+    Ok(imports)
+}
+
+/// Writes the dashboard of `tests`, given with their name and the path of their
+/// Wasm module for `version`, to `dashboard_path`. Tests without a Wasm module
+/// are left out.
+pub fn write_dashboard(
+    dashboard_path: &Path,
+    version: WasiVersion,
+    tests: &[(String, PathBuf, &WasiTest)],
+) -> io::Result<()> {
+    let mut summaries = vec![];
+    let mut coverage: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (name, wasm_path, test) in tests {
+        if !wasm_path.exists() {
+            warn!(
+                "`{}` isn't on the dashboard, `{}` is missing",
+                test.wasm_prog_name,
+                wasm_path.to_string_lossy()
+            );
+            continue;
+        }
+        let wasi_functions = wasi_imports(&fs::read(wasm_path)?)?;
+        for function in &wasi_functions {
+            coverage
+                .entry(function.clone())
+                .or_default()
+                .push(name.to_string());
+        }
+        summaries.push(TestSummary {
+            name: name.to_string(),
+            wasi_functions,
+            expected_result: test.result,
+            expected_stdout: test.stdout.clone(),
+            expected_stderr: test.stderr.clone(),
+        });
+    }
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    for tests in coverage.values_mut() {
+        tests.sort();
+    }
+    let uncovered = WASI_FUNCTIONS
+        .iter()
+        .filter(|function| !coverage.contains_key(**function))
+        .map(|function| function.to_string())
+        .collect::<Vec<String>>();
+    info!(
+        "{} of {} WASI functions are covered for {:?}",
+        WASI_FUNCTIONS.len() - uncovered.len(),
+        WASI_FUNCTIONS.len(),
+        version
+    );
+
+    let dashboard = Dashboard {
+        wasi_version: version.get_directory_name().to_string(),
+        tests: summaries,
+        coverage,
+        uncovered,
+    };
+    info!("Writing dashboard to {}", dashboard_path.to_string_lossy());
+    fs::write(dashboard_path, serde_json::to_string_pretty(&dashboard)?)
+}
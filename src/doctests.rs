@@ -0,0 +1,74 @@
+//! Extraction of ```` ```wasi ```` code blocks from doc comments, so that
+//! documentation examples can double as WASI regression tests.
+//!
+//! Each block is a complete program and is compiled verbatim. Like a regular
+//! test file, a block carries its own options in a leading `// WASI:` header.
+
+/// The info string marking a fenced code block as a WASI test.
+const WASI_FENCE_ATTRIBUTE: &str = "wasi";
+
+/// Returns the text after the doc comment marker if `line` is part of a doc
+/// comment (`///` or `//!`).
+fn strip_doc_comment(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    if line.starts_with("////") {
+        // four or more slashes is a regular comment, not a doc comment
+        return None;
+    }
+    let content = line
+        .strip_prefix("///")
+        .or_else(|| line.strip_prefix("//!"))?;
+    Some(content.strip_prefix(' ').unwrap_or(content))
+}
+
+/// Returns the code of every ```` ```wasi ```` fenced block in the doc comments
+/// of `source_code`, in order of appearance.
+///
+/// Blocks with any other info string (```` ```rust ````, ```` ```text ````, ...)
+/// are ignored. A block left open when its doc comment ends is closed there,
+/// as rustdoc does.
+pub fn extract_wasi_doctests(source_code: &str) -> Vec<String> {
+    let mut doctests = vec![];
+    // `Some((is_wasi, code))` while inside a fenced block
+    let mut current_block: Option<(bool, String)> = None;
+
+    for line in source_code.lines() {
+        let doc_line = match strip_doc_comment(line) {
+            Some(doc_line) => doc_line,
+            None => {
+                if let Some((true, code)) = current_block.take() {
+                    doctests.push(code);
+                }
+                continue;
+            }
+        };
+
+        match (
+            current_block.take(),
+            doc_line.trim_start().strip_prefix("```"),
+        ) {
+            (None, Some(info)) => {
+                let is_wasi = info
+                    .split(',')
+                    .any(|attribute| attribute.trim() == WASI_FENCE_ATTRIBUTE);
+                current_block = Some((is_wasi, String::new()));
+            }
+            (Some((is_wasi, code)), Some(_)) => {
+                if is_wasi {
+                    doctests.push(code);
+                }
+            }
+            (Some((is_wasi, mut code)), None) => {
+                code.push_str(doc_line);
+                code.push('\n');
+                current_block = Some((is_wasi, code));
+            }
+            (None, None) => (),
+        }
+    }
+    if let Some((true, code)) = current_block {
+        doctests.push(code);
+    }
+
+    doctests
+}
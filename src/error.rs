@@ -0,0 +1,266 @@
+//! The errors generating the tests can fail with. Each names the test file it
+//! comes from, so that a failing run says which test to look at.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::manifest::SizeChange;
+use super::wasi_version::WasiVersion;
+
+#[derive(Debug)]
+pub enum WasiTestError {
+    /// The compiler failed to compile the test
+    Compilation {
+        file: PathBuf,
+        /// What it was compiled for: native code or a WASI version
+        target: String,
+        /// The command line of the compiler
+        command: String,
+        stderr: String,
+    },
+    /// The native program didn't run the way its options expect
+    Execution {
+        file: PathBuf,
+        message: String,
+    },
+    /// A directive of the test's `// WASI:` header is malformed
+    Parse {
+        file: PathBuf,
+        message: String,
+    },
+    Io {
+        file: PathBuf,
+        source: io::Error,
+    },
+    /// The Wasm built for a WASI version doesn't validate
+    InvalidWasm {
+        file: PathBuf,
+        version: WasiVersion,
+        message: String,
+    },
+    /// The Wasm built for a WASI version imports WASI functions that its
+    /// `imports` don't allow, the ones listed
+    UnexpectedImports {
+        file: PathBuf,
+        version: WasiVersion,
+        imports: Vec<String>,
+    },
+    /// The Wasm built for a WASI version doesn't behave like the native program
+    /// when run with wasmer
+    WasmerMismatch {
+        file: PathBuf,
+        version: WasiVersion,
+        diff: String,
+    },
+    /// The native program of a test didn't behave the same when run twice,
+    /// with what differed
+    Nondeterministic {
+        file: PathBuf,
+        diff: String,
+    },
+    /// The `.wast`s generated for a test don't match the ones already
+    /// generated, with the diff of each
+    OutOfSync {
+        file: PathBuf,
+        wasts: Vec<(PathBuf, String)>,
+    },
+    /// The `.wast`s of these tests are out of date, when checking them
+    OutOfDate {
+        files: Vec<PathBuf>,
+    },
+    /// No test file matches the pattern they're looked for with
+    NoTests {
+        pattern: String,
+    },
+    /// The Wasm of some tests grew more than the tolerance over its baseline
+    WasmSizeRegression {
+        version: WasiVersion,
+        /// In percent
+        tolerance: f64,
+        regressions: Vec<SizeChange>,
+    },
+    /// The Rust toolchain a WASI version is built with isn't installed
+    ToolchainMissing {
+        version: WasiVersion,
+        toolchain: String,
+    },
+    /// The errors of every test that failed, when the build keeps going after
+    /// a failure. They're reported grouped by `kind`.
+    Tests(Vec<WasiTestError>),
+}
+
+/// The kinds of failures, in the order the failures of a build are reported
+const KINDS: [&str; 4] = ["compile", "run", "parse", "other"];
+
+impl WasiTestError {
+    /// Returns a function wrapping an IO error with the `file` it's about,
+    /// for `map_err`
+    pub(crate) fn io(file: &Path) -> impl Fn(io::Error) -> Self + Copy + '_ {
+        move |source| WasiTestError::Io {
+            file: file.to_path_buf(),
+            source,
+        }
+    }
+
+    /// What kind of failure it is: the test's compilation, its run, the
+    /// parsing of its options, or another one
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WasiTestError::Compilation { .. }
+            | WasiTestError::InvalidWasm { .. }
+            | WasiTestError::UnexpectedImports { .. }
+            | WasiTestError::ToolchainMissing { .. } => "compile",
+            WasiTestError::Execution { .. }
+            | WasiTestError::WasmerMismatch { .. }
+            | WasiTestError::Nondeterministic { .. } => "run",
+            WasiTestError::Parse { .. } => "parse",
+            _ => "other",
+        }
+    }
+}
+
+impl fmt::Display for WasiTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WasiTestError::Compilation {
+                file,
+                target,
+                command,
+                stderr,
+            } => write!(
+                f,
+                "Failed to compile `{}` for {}:\n$ {}\n{}",
+                file.to_string_lossy(),
+                target,
+                command,
+                stderr
+            ),
+            WasiTestError::Execution { file, message } => {
+                write!(f, "Failed to run `{}`: {}", file.to_string_lossy(), message)
+            }
+            WasiTestError::Parse { file, message } => write!(
+                f,
+                "Failed to parse the options of `{}`: {}",
+                file.to_string_lossy(),
+                message
+            ),
+            WasiTestError::Io { file, source } => {
+                write!(f, "IO error with `{}`: {}", file.to_string_lossy(), source)
+            }
+            WasiTestError::InvalidWasm {
+                file,
+                version,
+                message,
+            } => write!(
+                f,
+                "The Wasm of `{}` for WASI version {:?} is invalid: {}",
+                file.to_string_lossy(),
+                version,
+                message
+            ),
+            WasiTestError::UnexpectedImports {
+                file,
+                version,
+                imports,
+            } => write!(
+                f,
+                "The Wasm of `{}` for WASI version {:?} imports WASI functions its `imports` don't allow: {}",
+                file.to_string_lossy(),
+                version,
+                imports.join(", ")
+            ),
+            WasiTestError::Nondeterministic { file, diff } => write!(
+                f,
+                "`{}` is nondeterministic, its native program behaves differently from one run to the next, `scrub` what varies:\n{}",
+                file.to_string_lossy(),
+                diff
+            ),
+            WasiTestError::WasmerMismatch {
+                file,
+                version,
+                diff,
+            } => write!(
+                f,
+                "The Wasm of `{}` for WASI version {:?} doesn't run like the native program under wasmer:\n{}",
+                file.to_string_lossy(),
+                version,
+                diff
+            ),
+            WasiTestError::OutOfSync { file, wasts } => {
+                write!(
+                    f,
+                    "The output of `{}` changed, run with `--update` if that's expected:",
+                    file.to_string_lossy()
+                )?;
+                for (wast, diff) in wasts {
+                    write!(f, "\n`{}`:\n{}", wast.to_string_lossy(), diff)?;
+                }
+                Ok(())
+            }
+            WasiTestError::OutOfDate { files } => {
+                write!(
+                    f,
+                    "The `.wast`s of {} tests are out of date, generate them again or run with `--update`:",
+                    files.len()
+                )?;
+                for file in files {
+                    write!(f, "\n{}", file.to_string_lossy())?;
+                }
+                Ok(())
+            }
+            WasiTestError::NoTests { pattern } => {
+                write!(f, "No test file matches `{}`", pattern)
+            }
+            WasiTestError::WasmSizeRegression {
+                version,
+                tolerance,
+                regressions,
+            } => {
+                write!(
+                    f,
+                    "The Wasm of {} tests grew more than {}% for WASI version {:?}, run with `--update-size-baseline` if that's expected:",
+                    regressions.len(),
+                    tolerance,
+                    version
+                )?;
+                for regression in regressions {
+                    write!(f, "\n  {}", regression)?;
+                }
+                Ok(())
+            }
+            WasiTestError::ToolchainMissing { version, toolchain } => write!(
+                f,
+                "The `{}` toolchain of WASI version {:?} isn't installed, run with `--set-up-toolchain` to install it",
+                toolchain, version
+            ),
+            WasiTestError::Tests(errors) => {
+                write!(f, "{} tests failed to generate:", errors.len())?;
+                for kind in KINDS {
+                    let errors = errors
+                        .iter()
+                        .filter(|error| error.kind() == kind)
+                        .collect::<Vec<_>>();
+                    if errors.is_empty() {
+                        continue;
+                    }
+                    write!(f, "\n\n{} failures ({}):", kind, errors.len())?;
+                    for error in errors {
+                        write!(f, "\n\n{}", error)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for WasiTestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WasiTestError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
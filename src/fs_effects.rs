@@ -0,0 +1,137 @@
+//! Recording of the filesystem changes a program makes, so that the files it
+//! produces can be asserted on and reviewed like its output.
+//!
+//! The watched trees are snapshotted before and after the native run. The
+//! difference is the expectation, and the trees are then restored so that
+//! every run starts from the same state.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A change a program made to a file or directory. Paths are relative to the
+/// directory the tests run in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "effect", rename_all = "snake_case")]
+pub enum FsEffect {
+    /// The contents are `None` for a directory
+    Created {
+        path: String,
+        contents: Option<String>,
+    },
+    Modified {
+        path: String,
+        contents: String,
+    },
+    Deleted {
+        path: String,
+    },
+}
+
+/// The contents of every file (`Some`) and directory (`None`) in some trees
+#[derive(Debug, Default, PartialEq)]
+pub struct FsSnapshot {
+    entries: BTreeMap<PathBuf, Option<Vec<u8>>>,
+}
+
+impl FsSnapshot {
+    /// Snapshots the trees at `roots`, relative to `base_dir`. Missing roots
+    /// are snapshotted as empty.
+    pub fn take(base_dir: &Path, roots: &[String]) -> io::Result<Self> {
+        let mut snapshot = FsSnapshot::default();
+        for root in roots {
+            if base_dir.join(root).exists() {
+                snapshot.add(base_dir, Path::new(root))?;
+            }
+        }
+        Ok(snapshot)
+    }
+
+    fn add(&mut self, base_dir: &Path, path: &Path) -> io::Result<()> {
+        let full_path = base_dir.join(path);
+        if fs::symlink_metadata(&full_path)?.is_dir() {
+            self.entries.insert(path.to_path_buf(), None);
+            for entry in fs::read_dir(&full_path)? {
+                self.add(base_dir, &path.join(entry?.file_name()))?;
+            }
+        } else {
+            self.entries
+                .insert(path.to_path_buf(), Some(fs::read(&full_path)?));
+        }
+        Ok(())
+    }
+
+    /// The changes that turned `before` into this snapshot, in path order
+    pub fn effects_since(&self, before: &FsSnapshot) -> Vec<FsEffect> {
+        let mut effects = vec![];
+        for (path, contents) in &self.entries {
+            let path_str = path.to_string_lossy().to_string();
+            match (before.entries.get(path), contents) {
+                (None, contents) => effects.push(FsEffect::Created {
+                    path: path_str,
+                    contents: contents.as_ref().map(|c| contents_to_string(path, c)),
+                }),
+                (Some(Some(old)), Some(new)) if old != new => effects.push(FsEffect::Modified {
+                    path: path_str,
+                    contents: contents_to_string(path, new),
+                }),
+                _ => (),
+            }
+        }
+        for path in before.entries.keys() {
+            if !self.entries.contains_key(path) {
+                effects.push(FsEffect::Deleted {
+                    path: path.to_string_lossy().to_string(),
+                });
+            }
+        }
+        effects.sort_by(|a, b| a.path().cmp(b.path()));
+        effects
+    }
+
+    /// Puts the trees back the way they were in this snapshot, given their
+    /// `current` state.
+    pub fn restore(&self, base_dir: &Path, current: &FsSnapshot) -> io::Result<()> {
+        // deepest first, so directories are empty by the time they're removed
+        for (path, contents) in current.entries.iter().rev() {
+            if !self.entries.contains_key(path) {
+                match contents {
+                    Some(_) => fs::remove_file(base_dir.join(path))?,
+                    None => fs::remove_dir_all(base_dir.join(path))?,
+                }
+            }
+        }
+        // parents first, so files have a directory to be written to
+        for (path, contents) in &self.entries {
+            if current.entries.get(path) == Some(contents) {
+                continue;
+            }
+            match contents {
+                Some(contents) => fs::write(base_dir.join(path), contents)?,
+                None => fs::create_dir_all(base_dir.join(path))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FsEffect {
+    pub fn path(&self) -> &str {
+        match self {
+            FsEffect::Created { path, .. }
+            | FsEffect::Modified { path, .. }
+            | FsEffect::Deleted { path } => path,
+        }
+    }
+}
+
+fn contents_to_string(path: &Path, contents: &[u8]) -> String {
+    String::from_utf8(contents.to_vec()).unwrap_or_else(|_| {
+        warn!(
+            "`{}` isn't UTF-8, its recorded contents are lossy",
+            path.to_string_lossy()
+        );
+        String::from_utf8_lossy(contents).to_string()
+    })
+}
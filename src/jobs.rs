@@ -0,0 +1,58 @@
+//! Bounding how many compilations run at once, as each `rustc` is already
+//! parallel itself and too many of them at once thrash the machine.
+
+use std::num::NonZeroUsize;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// The default `BuildOptions::max_parallelism`: the number of CPUs, or no
+/// bound if it can't be known
+pub fn default_parallelism() -> Option<usize> {
+    thread::available_parallelism().map(NonZeroUsize::get).ok()
+}
+
+/// The slots of the jobs that may run at once, shared by the threads running
+/// them
+#[derive(Debug)]
+pub struct JobSlots {
+    /// The most jobs running at once, or `None` for no bound
+    max: Option<usize>,
+    running: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// A taken slot, freed when dropped
+#[derive(Debug)]
+pub struct JobSlot<'a> {
+    slots: &'a JobSlots,
+}
+
+impl JobSlots {
+    /// The slots of at most `max` jobs, with `None` or `0` for no bound
+    pub fn new(max: Option<usize>) -> Self {
+        JobSlots {
+            max: max.filter(|&max| max != 0),
+            running: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Takes a slot, waiting for one to be freed if they're all taken
+    pub fn take(&self) -> JobSlot<'_> {
+        let mut running = self.running.lock().unwrap();
+        if let Some(max) = self.max {
+            while *running >= max {
+                running = self.freed.wait(running).unwrap();
+            }
+        }
+        *running += 1;
+        JobSlot { slots: self }
+    }
+}
+
+impl Drop for JobSlot<'_> {
+    fn drop(&mut self) {
+        *self.slots.running.lock().unwrap() -= 1;
+        self.slots.freed.notify_one();
+    }
+}
@@ -1,30 +1,156 @@
 #[macro_use]
+extern crate log;
+#[macro_use]
 extern crate serde;
 
+mod compiler;
+mod dashboard;
+mod doctests;
+mod error;
+mod fs_effects;
+mod jobs;
+mod manifest;
 mod set_up_toolchain;
+mod timings;
 mod util;
 mod wasi_version;
 mod wasitests;
+mod wast_parser;
 
+pub use crate::error::WasiTestError;
+pub use crate::fs_effects::FsEffect;
 pub use crate::set_up_toolchain::install_toolchains;
-pub use crate::wasi_version::{WasiVersion, ALL_WASI_VERSIONS, LATEST_WASI_VERSION};
-pub use crate::wasitests::{build, WasiOptions, WasiTest};
+pub use crate::util::parse_size;
+pub use crate::wasi_version::{
+    UnknownWasiVersion, WasiVersion, ALL_WASI_VERSIONS, LATEST_WASI_VERSION,
+};
+pub use crate::wasitests::{
+    build, build_filtered, build_single, check, clean, generate_test, BuildOptions, ExpectedExit,
+    ExpectedReturn, GeneratedTest, NativeOutput, TestFilter, WasiClock, WasiOptions, WasiTest,
+    WasiTestMeta, DEFAULT_EXECUTE_DIR, DEFAULT_NATIVE_TIMEOUT, DEFAULT_SIZE_TOLERANCE,
+    RANDOM_SEED_VAR, SCRUB_PLACEHOLDER,
+};
+pub use crate::wast_parser::ParseError;
 
 use gumdrop::Options;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Options)]
 pub struct TestGenOptions {
     /// Whether or not to do operations for all versions of WASI or just the latest.
     all_versions: bool,
+    /// The only version of WASI to do operations for, by name (like `snapshot1`).
+    #[options(no_short, meta = "VERSION")]
+    wasi_version: Option<WasiVersion>,
     /// Whether or not the Wasm will be generated.
     generate_wasm: bool,
     /// Whether or not the logic to install the needed Rust compilers is run.
     set_up_toolchain: bool,
+    /// Whether or not the previously generated files are removed first.
+    #[options(no_short)]
+    clean: bool,
+    /// Whether or not tests are also generated from the ```wasi blocks in doc comments.
+    doctests: bool,
+    /// Whether or not a dashboard of what the tests validate is written for each version.
+    #[options(no_short)]
+    dashboard: bool,
+    /// Whether or not tests are generated even if they're up to date.
+    force: bool,
+    /// The number of seconds after which a native test program is killed (default: 30).
+    timeout: Option<u64>,
+    /// Whether or not each native test program is run twice, failing the tests whose output differs between the runs.
+    #[options(no_short)]
+    check_determinism: bool,
+    /// How many times a compilation failing for what looks like a transient reason, like a busy machine, is tried (default: 1).
+    #[options(no_short, meta = "N")]
+    compile_attempts: Option<usize>,
+    /// Whether or not wasm-strip and wasm-opt are skipped, leaving the Wasm as rustc outputs it.
+    #[options(no_short)]
+    skip_post_processing: bool,
+    /// The most Wasm compilations run at once, 0 for no bound (default: the number of CPUs).
+    #[options(no_short, meta = "N")]
+    max_parallelism: Option<usize>,
+    /// Whether or not the text format of each generated Wasm is written to a .wat, with wasm2wat.
+    #[options(no_short)]
+    emit_wat: bool,
+    /// The version a post-processing tool is expected to have, like `wasm-opt=116`, warning otherwise (repeatable).
+    #[options(no_short, meta = "TOOL=VERSION")]
+    pin_tool: Vec<String>,
+    /// Whether or not the generated Wasm isn't checked to be valid.
+    #[options(no_short)]
+    skip_validation: bool,
+    /// Whether or not each generated test is run with wasmer to check it behaves like the native program.
+    #[options(no_short)]
+    verify: bool,
+    /// The wasmer binary the tests are verified with, implying --verify (default: `wasmer`).
+    #[options(no_short, meta = "PATH")]
+    wasmer: Option<PathBuf>,
+    /// Whether or not the current Wasm sizes become the baseline they're compared to.
+    #[options(no_short)]
+    update_size_baseline: bool,
+    /// How much a Wasm module may grow over its baseline, in percent (default: 10).
+    #[options(no_short, meta = "PERCENT")]
+    size_tolerance: Option<f64>,
+    /// Whether or not \r\n is turned into \n in the native output (always on Windows).
+    #[options(no_short)]
+    normalize_line_endings: bool,
+    /// Whether or not the other tests are still generated when one fails.
+    keep_going: bool,
+    /// Whether or not a missing toolchain fails the generation rather than skipping its version.
+    #[options(no_short)]
+    strict_toolchains: bool,
+    /// Whether or not the source of a test that fails to compile to Wasm is kept in target/failed-sources.
+    #[options(no_short)]
+    keep_temp: bool,
+    /// The directory the native tests run in, which their paths are relative to (default: wasi).
+    #[options(no_short, meta = "DIR")]
+    execute_dir: Option<PathBuf>,
+    /// Only generate the tests whose name contains PATTERN, or matches it if it's a glob like `fd_*`.
+    #[options(no_short, meta = "PATTERN")]
+    filter: Option<TestFilter>,
+    /// Don't treat the test files whose name matches the glob PATTERN as tests, like helper modules (repeatable).
+    #[options(no_short, meta = "PATTERN")]
+    exclude: Vec<glob::Pattern>,
+    /// The directory the generated files are written to, in a directory per version (default: wasi, next to wasi/tests).
+    #[options(no_short, meta = "DIR")]
+    output_root: Option<PathBuf>,
+    /// Whether or not finding no test file fails the generation rather than warning.
+    #[options(no_short)]
+    require_tests: bool,
+    /// Whether or not the generated files of tests whose source was removed are removed too.
+    #[options(no_short)]
+    prune: bool,
+    /// Whether or not the tests are only run natively and their .wast printed, writing and removing no file.
+    #[options(no_short)]
+    dry_run: bool,
+    /// Whether or not the tests are only run natively to check their .wast is up to date, writing no file.
+    #[options(no_short)]
+    check: bool,
+    /// Whether or not up-to-date tests are run natively too, rewriting the .wast of those whose output changed.
+    #[options(no_short)]
+    update: bool,
+    /// Another target the native programs are built for, and run if they can, to add its output to the .wast where it differs (repeatable).
+    #[options(no_short, meta = "TRIPLE")]
+    native_target: Vec<String>,
+    /// The Rust compiler used for every version instead of rustup's toolchains (default: $RUSTC).
+    #[options(no_short, meta = "PATH")]
+    rustc: Option<PathBuf>,
+    /// The C compiler the C tests are built natively with (default: `cc`).
+    #[options(no_short, meta = "PATH")]
+    cc: Option<PathBuf>,
+    /// The clang of wasi-sdk the C tests are built to Wasm with (default: `clang`).
+    #[options(no_short, meta = "PATH")]
+    clang: Option<PathBuf>,
     /// Print the help message
     help: bool,
 }
 
 fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
     let opts = TestGenOptions::parse_args_default_or_exit();
 
     if opts.help {
@@ -35,10 +161,10 @@ fn main() {
     let generate_all = opts.all_versions;
     let set_up_toolchain = opts.set_up_toolchain;
     let generate_wasm = opts.generate_wasm;
-    let wasi_versions = if generate_all {
-        ALL_WASI_VERSIONS
-    } else {
-        LATEST_WASI_VERSION
+    let wasi_versions = match &opts.wasi_version {
+        Some(version) => std::slice::from_ref(version),
+        None if generate_all => ALL_WASI_VERSIONS,
+        None => LATEST_WASI_VERSION,
     };
 
     // Install the Rust WASI toolchains for each of the versions
@@ -46,8 +172,81 @@ fn main() {
         install_toolchains(wasi_versions);
     }
 
+    // Remove the files of the previous generation
+    if opts.clean {
+        if let Err(e) = clean(wasi_versions, opts.output_root.as_deref()) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
     // Generate the WASI Wasm files
     if generate_wasm {
-        build(wasi_versions);
+        if opts.check && opts.update {
+            error!("--check and --update can't be used together");
+            std::process::exit(1);
+        }
+        let mut pinned_tools = vec![];
+        for pin in &opts.pin_tool {
+            match pin.split_once('=') {
+                Some((tool, version)) => pinned_tools.push((tool.to_string(), version.to_string())),
+                None => {
+                    error!(
+                        "--pin-tool expects TOOL=VERSION, like `wasm-opt=116`: `{}`",
+                        pin
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        let build_options = BuildOptions {
+            doctests: opts.doctests,
+            dashboard: opts.dashboard,
+            force: opts.force,
+            native_timeout: opts
+                .timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_NATIVE_TIMEOUT),
+            check_determinism: opts.check_determinism,
+            compile_attempts: opts.compile_attempts.unwrap_or(1),
+            skip_post_processing: opts.skip_post_processing,
+            max_parallelism: opts.max_parallelism.or_else(jobs::default_parallelism),
+            pinned_tools,
+            emit_wat: opts.emit_wat,
+            validate_wasm: !opts.skip_validation,
+            verify_with_wasmer: opts
+                .wasmer
+                .clone()
+                .or_else(|| opts.verify.then(|| PathBuf::from("wasmer"))),
+            update_size_baseline: opts.update_size_baseline,
+            size_tolerance: opts.size_tolerance.unwrap_or(DEFAULT_SIZE_TOLERANCE),
+            normalize_line_endings: opts.normalize_line_endings || cfg!(windows),
+            keep_going: opts.keep_going,
+            strict_toolchains: opts.strict_toolchains,
+            keep_temp: opts.keep_temp,
+            execute_dir: opts
+                .execute_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_EXECUTE_DIR)),
+            filter: opts.filter.clone(),
+            exclude: opts.exclude.clone(),
+            output_root: opts.output_root.clone(),
+            require_tests: opts.require_tests,
+            prune: opts.prune,
+            dry_run: opts.dry_run,
+            check: opts.check,
+            update: opts.update,
+            native_targets: opts.native_target.clone(),
+            rustc: opts
+                .rustc
+                .clone()
+                .or_else(|| std::env::var_os("RUSTC").map(PathBuf::from)),
+            cc: opts.cc.clone().unwrap_or_else(|| PathBuf::from("cc")),
+            clang: opts.clang.clone().unwrap_or_else(|| PathBuf::from("clang")),
+        };
+        if let Err(e) = build(wasi_versions, &build_options) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,172 @@
+//! A `manifest.json` in each version's directory, indexing the generated tests
+//! so that harnesses don't have to find the `.wast` files themselves, and so
+//! that a dropped or renamed test shows up in its diff.
+//!
+//! Like the dashboard, it's sorted so it only changes when the tests do.
+//!
+//! It also holds the size of each Wasm module and the baseline it's compared
+//! to, so that the modules growing doesn't go unnoticed, and the versions of
+//! the tools that post-processed them, which change them when upgraded.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::wasi_version::WasiVersion;
+use super::wasitests::WasiOptions;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    wasi_version: String,
+    /// The version of each tool the Wasm modules were last post-processed
+    /// with, by name
+    #[serde(default)]
+    post_processing_tools: BTreeMap<String, String>,
+    tests: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The path of the test's `.wast`, relative to the version's directory and
+    /// without the extension
+    pub name: String,
+    /// The path of its Wasm module, relative to the version's directory, or
+    /// `None` if it's skipped
+    pub wasm: Option<String>,
+    /// The path of the source it's generated from, relative to the `wasi`
+    /// directory
+    pub source: String,
+    /// Why the test isn't generated, if it's skipped with `// skip`
+    pub skipped: Option<String>,
+    pub options: WasiOptions,
+    /// The size of its Wasm module in bytes
+    pub wasm_size: Option<u64>,
+    /// The size its Wasm module is compared to, which is only updated on
+    /// request
+    pub wasm_size_baseline: Option<u64>,
+}
+
+/// A Wasm module whose size differs from its baseline
+#[derive(Debug)]
+pub struct SizeChange {
+    pub name: String,
+    pub baseline: u64,
+    pub size: u64,
+}
+
+impl SizeChange {
+    /// How much the module grew, in percent of its baseline
+    pub fn growth(&self) -> f64 {
+        (self.size as f64 - self.baseline as f64) * 100.0 / self.baseline as f64
+    }
+}
+
+impl fmt::Display for SizeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {} bytes ({:+.1}%)",
+            self.name,
+            self.baseline,
+            self.size,
+            self.growth()
+        )
+    }
+}
+
+/// Writes the manifest of `tests` for `version` to `manifest_path`. It's
+/// written to a temporary file first, so that a reader never sees it partly
+/// written.
+pub fn write_manifest(
+    manifest_path: &Path,
+    version: WasiVersion,
+    post_processing_tools: BTreeMap<String, String>,
+    mut tests: Vec<ManifestEntry>,
+) -> io::Result<()> {
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    let manifest = Manifest {
+        wasi_version: version.get_directory_name().to_string(),
+        post_processing_tools,
+        tests,
+    };
+
+    info!("Writing manifest to {}", manifest_path.to_string_lossy());
+    let temp_path = manifest_path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_string_pretty(&manifest)?)?;
+    fs::rename(&temp_path, manifest_path)
+}
+
+/// Reads the Wasm size baselines of the manifest at `manifest_path`, by test
+/// name. There are none if it doesn't exist yet or can't be read, in which
+/// case the current sizes become the baselines.
+pub fn read_size_baselines(manifest_path: &Path) -> io::Result<HashMap<String, u64>> {
+    let json = match fs::read_to_string(manifest_path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let manifest: Manifest = match serde_json::from_str(&json) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            warn!(
+                "Ignoring the size baselines of {}: {}",
+                manifest_path.to_string_lossy(),
+                e
+            );
+            return Ok(HashMap::new());
+        }
+    };
+    Ok(manifest
+        .tests
+        .into_iter()
+        .filter_map(|entry| Some((entry.name, entry.wasm_size_baseline?)))
+        .collect())
+}
+
+/// Reads the versions of the post-processing tools of the manifest at
+/// `manifest_path`, by tool name. There are none if it doesn't exist yet or
+/// can't be read.
+pub fn read_post_processing_tools(manifest_path: &Path) -> io::Result<BTreeMap<String, String>> {
+    let json = match fs::read_to_string(manifest_path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+    // a manifest that can't be read is already warned about with its baselines
+    Ok(serde_json::from_str::<Manifest>(&json)
+        .map(|manifest| manifest.post_processing_tools)
+        .unwrap_or_default())
+}
+
+/// Sets the Wasm size baseline of the `tests`: their current size if `update`
+/// or they have none in `baselines`, and the one in `baselines` otherwise.
+/// Returns the tests whose size differs from their baseline.
+pub fn apply_size_baselines(
+    tests: &mut [ManifestEntry],
+    baselines: &HashMap<String, u64>,
+    update: bool,
+) -> Vec<SizeChange> {
+    let mut changes = vec![];
+    for test in tests {
+        let size = match test.wasm_size {
+            Some(size) => size,
+            None => continue,
+        };
+        let baseline = match baselines.get(&test.name) {
+            Some(&baseline) if !update => baseline,
+            _ => size,
+        };
+        test.wasm_size_baseline = Some(baseline);
+        if size != baseline {
+            changes.push(SizeChange {
+                name: test.name.clone(),
+                baseline,
+                size,
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    changes
+}
@@ -3,8 +3,9 @@ use super::wasi_version::*;
 
 use std::process::Command;
 
-fn install_toolchain(toolchain_name: &str) {
-    println!("Installing rustup toolchain: {}", toolchain_name);
+fn install_toolchain(wasi_version: WasiVersion) {
+    let toolchain_name = wasi_version.get_compiler_toolchain();
+    info!("Installing rustup toolchain: {}", toolchain_name);
     let rustup_out = Command::new("rustup")
         .arg("toolchain")
         .arg("install")
@@ -13,23 +14,31 @@ fn install_toolchain(toolchain_name: &str) {
         .expect("Failed to install toolchain with rustup");
     util::print_info_on_error(&rustup_out, "TOOLCHAIN INSTALL FAILED");
 
-    println!("Installing rustup WASI target");
-    let rustup_out = Command::new("rustup")
-        .arg("target")
-        .arg("add")
-        .arg("wasm32-wasi")
-        .arg("--toolchain")
-        .arg(toolchain_name)
-        .output()
-        .expect("Failed to wasi target in Rust toolchain");
-    util::print_info_on_error(&rustup_out, "WASI TARGET IN TOOLCHAIN INSTALL FAILED");
+    // the toolchain only knows one of the target's names, so they're tried in
+    // turn and only the failure of the last is reported
+    let triples = wasi_version.get_target_triples();
+    for (i, triple) in triples.iter().enumerate() {
+        info!("Installing rustup WASI target {}", triple);
+        let rustup_out = Command::new("rustup")
+            .arg("target")
+            .arg("add")
+            .arg(triple)
+            .arg("--toolchain")
+            .arg(toolchain_name)
+            .output()
+            .expect("Failed to wasi target in Rust toolchain");
+        if rustup_out.status.success() || i == triples.len() - 1 {
+            util::print_info_on_error(&rustup_out, "WASI TARGET IN TOOLCHAIN INSTALL FAILED");
+            break;
+        }
+    }
 }
 
 pub fn install_toolchains(wasi_versions: &[WasiVersion]) {
-    println!("Setting up system to generate the WASI tests.");
-    println!("WARNING: this may use a lot of disk space.");
+    info!("Setting up system to generate the WASI tests.");
+    warn!("this may use a lot of disk space.");
 
     for wasi_version in wasi_versions {
-        install_toolchain(wasi_version.get_compiler_toolchain());
+        install_toolchain(*wasi_version);
     }
 }
@@ -0,0 +1,75 @@
+//! How long each phase of generating a test took, summed up in a table at the
+//! end of the run so that the slow tests stand out.
+
+use std::time::Duration;
+
+/// The time spent building and running a test
+#[derive(Debug, Clone, Default)]
+pub struct TestTimings {
+    pub native_compile: Duration,
+    pub native_run: Duration,
+    /// One per WASI version the test was generated for
+    pub wasm: Vec<WasmTimings>,
+}
+
+/// The time spent building the Wasm module of a test for one WASI version
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WasmTimings {
+    pub compile: Duration,
+    /// Its post-processing and validation
+    pub post_process: Duration,
+}
+
+impl TestTimings {
+    pub fn wasm_compile(&self) -> Duration {
+        self.wasm.iter().map(|wasm| wasm.compile).sum()
+    }
+
+    pub fn wasm_post_process(&self) -> Duration {
+        self.wasm.iter().map(|wasm| wasm.post_process).sum()
+    }
+
+    /// The time spent in every phase. As the versions are compiled in
+    /// parallel, it's more than the test took when there are several.
+    pub fn total(&self) -> Duration {
+        self.native_compile + self.native_run + self.wasm_compile() + self.wasm_post_process()
+    }
+}
+
+fn duration(duration: Duration) -> String {
+    format!("{:.2?}", duration)
+}
+
+/// A table of the `timings` by test name, the slowest first
+pub fn timings_table(timings: &mut [(String, TestTimings)]) -> String {
+    timings.sort_by(|(a_name, a), (b_name, b)| b.total().cmp(&a.total()).then(a_name.cmp(b_name)));
+    let name_width = timings
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(std::iter::once("test".len()))
+        .max()
+        .unwrap();
+    let mut table = format!(
+        "{:<name_width$}  {:>10}  {:>12}  {:>10}  {:>10}  {:>15}",
+        "test",
+        "total",
+        "native build",
+        "native run",
+        "wasm build",
+        "post-processing",
+        name_width = name_width
+    );
+    for (name, test) in timings.iter() {
+        table.push_str(&format!(
+            "\n{:<name_width$}  {:>10}  {:>12}  {:>10}  {:>10}  {:>15}",
+            name,
+            duration(test.total()),
+            duration(test.native_compile),
+            duration(test.native_run),
+            duration(test.wasm_compile()),
+            duration(test.wasm_post_process()),
+            name_width = name_width
+        ));
+    }
+    table
+}
@@ -1,13 +1,351 @@
 pub fn print_info_on_error(output: &std::process::Output, context: &str) {
     if !output.status.success() {
-        println!("{}", context);
-        println!(
-            "stdout:\n{}",
-            std::str::from_utf8(&output.stdout[..]).unwrap()
-        );
-        eprintln!(
-            "stderr:\n{}",
+        error!(
+            "{}\nstdout:\n{}\nstderr:\n{}",
+            context,
+            std::str::from_utf8(&output.stdout[..]).unwrap(),
             std::str::from_utf8(&output.stderr[..]).unwrap()
         );
     }
 }
+
+/// A diff of the lines of `expected` and `actual`, for error messages: the
+/// lines between their common beginning and end, `-` for the expected ones and
+/// `+` for the actual ones.
+pub fn diff_lines(expected: &str, actual: &str) -> String {
+    let expected = expected.split_inclusive('\n').collect::<Vec<&str>>();
+    let actual = actual.split_inclusive('\n').collect::<Vec<&str>>();
+    let prefix = expected
+        .iter()
+        .zip(&actual)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut out = vec![format!("@@ line {} @@", prefix + 1)];
+    for (sign, lines) in [
+        ('-', &expected[prefix..expected.len() - suffix]),
+        ('+', &actual[prefix..actual.len() - suffix]),
+    ] {
+        out.extend(lines.iter().map(|line| format!("{}{:?}", sign, line)));
+    }
+    out.join("\n")
+}
+
+/// A 64-bit FNV-1a hash: unlike `DefaultHasher`, it's stable across Rust
+/// versions, so it can be stored.
+pub fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Formats `s` as a wast string literal. Tabs, newlines, carriage returns,
+/// quotes and backslashes get their named escapes and other control
+/// characters are escaped byte by byte, as `\hh`.
+pub fn wast_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let mut buf = [0; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    out.push_str(&format!("\\{:02x}", byte));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Formats `bytes` as a wast string literal, which can hold any bytes.
+/// Printable ASCII is kept as is, everything else is escaped as `\hh`.
+pub fn wast_bytes(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 2);
+    out.push('"');
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => out.push_str(&format!("\\{}", byte as char)),
+            0x20..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:02x}", byte)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Resolves the escapes of a quoted directive value: `\n`, `\r`, `\t`, `\0`,
+/// `\"`, `\\` and `\u{...}`.
+pub fn unescape_directive(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some('u') => {
+                let rest = chars
+                    .as_str()
+                    .strip_prefix('{')
+                    .ok_or_else(|| format!("expected `\\u{{...}}` in {:?}", s))?;
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| format!("unterminated `\\u{{...}}` escape in {:?}", s))?;
+                let hex = &rest[..end];
+                let c = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                    .ok_or_else(|| format!("invalid unicode escape `\\u{{{}}}` in {:?}", hex, s))?;
+                out.push(c);
+                chars = rest[end + 1..].chars();
+            }
+            Some(other) => return Err(format!("unknown escape `\\{}` in {:?}", other, s)),
+            None => return Err(format!("trailing `\\` in {:?}", s)),
+        }
+    }
+    Ok(out)
+}
+
+/// Splits a directive value into words like a POSIX shell: words are
+/// separated by whitespace, `'...'` quotes literally, `"..."` quotes with `\"`
+/// and `\\` escapes, and a backslash outside quotes escapes the next character.
+/// `""` is an empty word.
+pub fn split_shell_words(s: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    // `None` between words, so that quotes can make an empty word
+    let mut word: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unclosed `'` in {:?}", s)),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(format!("unclosed `\"` in {:?}", s)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unclosed `\"` in {:?}", s)),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(format!("trailing `\\` in {:?}", s)),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Parses a size in bytes, like `65536`, or with a binary unit, like `16MiB`
+/// or `64 KiB`. Returns what's wrong if it's malformed, isn't a whole number,
+/// has another unit, like the decimal `MB`, or doesn't fit in a `u64`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let trimmed = s.trim();
+    let digits = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(digits);
+    if number.is_empty() {
+        return Err(format!("`{}` doesn't start with a number of bytes", s));
+    }
+    let multiplier: u64 = match unit.trim_start() {
+        "" | "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        unit if unit.starts_with(['.', ',']) => {
+            return Err(format!("`{}` isn't a whole number", s));
+        }
+        // `K`, `MB` or `mib` could be either a decimal or a binary unit
+        unit => {
+            return Err(format!(
+                "`{}` has the unit `{}`, expected none, `B`, `KiB`, `MiB` or `GiB`",
+                s, unit
+            ));
+        }
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("`{}` is too large", s))
+}
+
+/// Decodes standard base64, with or without its `=` padding. Returns what's
+/// wrong if it's malformed.
+pub fn decode_base64(s: &str) -> Result<Vec<u8>, String> {
+    let data = s.trim_end_matches('=');
+    let padding = s.len() - data.len();
+    // the padding can only complete the last group of 4 characters
+    let valid = match (data.len() % 4, padding) {
+        (0, 0) => true,
+        (rest @ (2 | 3), padding) => padding == 0 || rest + padding == 4,
+        _ => false,
+    };
+    if !valid {
+        return Err(format!("`{}` has the wrong length or padding", s));
+    }
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut bits, mut bit_count) = (0u32, 0);
+    for (i, c) in data.char_indices() {
+        let value = match c {
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' => 62,
+            '/' => 63,
+            _ => return Err(format!("invalid character {:?} at {}", c, i)),
+        };
+        bits = (bits << 6) | value;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Writes `contents` to `path` through a temporary file next to it, renamed
+/// into place, so that an interrupted write leaves either the old or the new
+/// complete file, never a truncated one
+pub fn write_atomically(path: &std::path::Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    std::fs::write(&temp_path, contents)?;
+    std::fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescape_directive_escapes() {
+        assert_eq!(
+            unescape_directive(r#"a\tb\u{41}\u{1F600}\"\\"#).unwrap(),
+            "a\tbA\u{1F600}\"\\"
+        );
+        assert!(unescape_directive(r"\u41").is_err());
+        assert!(unescape_directive(r"\u{zz}").is_err());
+        assert!(unescape_directive(r"\q").is_err());
+        assert!(unescape_directive("\\").is_err());
+    }
+
+    #[test]
+    fn split_shell_words_quoting() {
+        let words = |s: &str| split_shell_words(s).unwrap();
+        assert_eq!(words(r#"--name "hello world""#), ["--name", "hello world"]);
+        assert_eq!(words(r#""""#), [""]);
+        assert_eq!(words("a '' b"), ["a", "", "b"]);
+        assert_eq!(words(r"'it'\''s'"), ["it's"]);
+        assert_eq!(words(r#""say \"hi\"" a\ b"#), [r#"say "hi""#, "a b"]);
+        assert_eq!(words("  spaced   out  "), ["spaced", "out"]);
+        assert!(words("").is_empty());
+        assert!(split_shell_words(r#"--name "hello"#).is_err());
+        assert!(split_shell_words("'it").is_err());
+        assert!(split_shell_words(r"trailing\").is_err());
+    }
+
+    #[test]
+    fn parse_size_table() {
+        let valid: &[(&str, u64)] = &[
+            ("0", 0),
+            ("16", 16),
+            ("16B", 16),
+            ("16KiB", 16 << 10),
+            ("64 KiB", 64 << 10),
+            ("16MiB", 16 << 20),
+            ("1GiB", 1 << 30),
+            (" 2GiB ", 2 << 30),
+        ];
+        for &(s, size) in valid {
+            assert_eq!(parse_size(s), Ok(size), "{:?}", s);
+        }
+        let invalid = [
+            "",
+            "KiB",
+            "-1",
+            "1.5MiB",
+            "16MB",
+            "16M",
+            "16kib",
+            "16 KiB extra",
+            "99999999999GiB",
+            "99999999999999999999",
+        ];
+        for s in invalid {
+            assert!(parse_size(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn decode_base64_padding() {
+        for (s, decoded) in [
+            ("", ""),
+            ("QUJD", "ABC"),
+            ("QUI=", "AB"),
+            ("QUI", "AB"),
+            ("QQ==", "A"),
+            ("QQ", "A"),
+            ("SGVsbG8=", "Hello"),
+        ] {
+            assert_eq!(decode_base64(s).as_deref(), Ok(decoded.as_bytes()), "{}", s);
+        }
+        for s in [
+            "QUJD=", "QUJD====", "QUI==", "QQ=", "QQ===", "Q", "Q===", "=", "QUJ*",
+        ] {
+            assert!(decode_base64(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn unescape_directive_unterminated_unicode() {
+        for s in [r"\u{41", r"\u{", r"abc\u{1F600"] {
+            let e = unescape_directive(s).unwrap_err();
+            assert!(e.contains("unterminated"), "{}", e);
+        }
+    }
+}
@@ -1,11 +1,22 @@
-pub static ALL_WASI_VERSIONS: &[WasiVersion] = &[WasiVersion::Unstable, WasiVersion::Snapshot1];
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+pub static ALL_WASI_VERSIONS: &[WasiVersion] = &[
+    WasiVersion::Unstable,
+    WasiVersion::Snapshot1,
+    WasiVersion::Preview2,
+];
 pub static LATEST_WASI_VERSION: &[WasiVersion] = &[WasiVersion::get_latest()];
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WasiVersion {
     /// A.K.A. Snapshot0
     Unstable,
     Snapshot1,
+    /// The component model's WASI, whose programs are components rather than
+    /// core modules
+    Preview2,
 }
 
 impl WasiVersion {
@@ -13,10 +24,36 @@ impl WasiVersion {
         Self::Snapshot1
     }
 
+    /// Every WASI version, oldest first
+    pub fn all() -> &'static [WasiVersion] {
+        ALL_WASI_VERSIONS
+    }
+
     pub fn get_compiler_toolchain(&self) -> &'static str {
         match self {
             WasiVersion::Unstable => "nightly-2019-09-13",
             WasiVersion::Snapshot1 => "nightly-2019-12-18",
+            WasiVersion::Preview2 => "nightly-2024-11-01",
+        }
+    }
+
+    /// The names of the Rust target for this version, newest first: since
+    /// Rust 1.78, `wasm32-wasi` is called `wasm32-wasip1`.
+    pub fn get_target_triples(&self) -> &'static [&'static str] {
+        match self {
+            WasiVersion::Unstable => &["wasm32-wasi"],
+            WasiVersion::Snapshot1 => &["wasm32-wasip1", "wasm32-wasi"],
+            WasiVersion::Preview2 => &["wasm32-wasip2"],
+        }
+    }
+
+    /// The target the `clang` of wasi-sdk builds C programs for this version
+    /// with, if it still does: it dropped `wasi_unstable` long ago.
+    pub fn get_c_target_triple(&self) -> Option<&'static str> {
+        match self {
+            WasiVersion::Unstable => None,
+            WasiVersion::Snapshot1 => Some("wasm32-wasi"),
+            WasiVersion::Preview2 => Some("wasm32-wasip2"),
         }
     }
 
@@ -24,6 +61,85 @@ impl WasiVersion {
         match self {
             WasiVersion::Unstable => "unstable",
             WasiVersion::Snapshot1 => "snapshot1",
+            WasiVersion::Preview2 => "preview2",
+        }
+    }
+
+    /// The unstable features the tests are compiled to Wasm with, unless they
+    /// declare them already. The old nightlies need `wasi_ext` for
+    /// `std::os::wasi`, which is stable by the toolchain of `Preview2`.
+    pub fn get_required_features(&self) -> &'static [&'static str] {
+        match self {
+            WasiVersion::Unstable | WasiVersion::Snapshot1 => &["wasi_ext"],
+            WasiVersion::Preview2 => &[],
+        }
+    }
+
+    /// Whether the programs built for this version are components rather
+    /// than core modules, which runners instantiate differently
+    pub fn is_component(&self) -> bool {
+        match self {
+            WasiVersion::Unstable | WasiVersion::Snapshot1 => false,
+            WasiVersion::Preview2 => true,
+        }
+    }
+}
+
+/// The error of parsing a string that doesn't name a WASI version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownWasiVersion(pub String);
+
+impl fmt::Display for UnknownWasiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = WasiVersion::all()
+            .iter()
+            .map(|version| format!("`{}`", version.get_directory_name()))
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(
+            f,
+            "unknown WASI version `{}`, expected one of {}",
+            self.0, names
+        )
+    }
+}
+
+impl Error for UnknownWasiVersion {}
+
+impl FromStr for WasiVersion {
+    type Err = UnknownWasiVersion;
+
+    /// Parses the directory name of a version, or one of its other names:
+    /// its module name (`wasi_unstable`, `wasi_snapshot_preview1`) or
+    /// `snapshot0`, `preview1`, `wasip1`, `wasip2` and `latest`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "unstable" | "snapshot0" | "wasi_unstable" => Ok(WasiVersion::Unstable),
+            "snapshot1" | "preview1" | "wasip1" | "wasi_snapshot_preview1" => {
+                Ok(WasiVersion::Snapshot1)
+            }
+            "preview2" | "wasip2" => Ok(WasiVersion::Preview2),
+            "latest" => Ok(WasiVersion::get_latest()),
+            _ => Err(UnknownWasiVersion(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_name_parses_back() {
+        for version in WasiVersion::all() {
+            assert_eq!(version.get_directory_name().parse(), Ok(*version));
         }
+        assert_eq!("wasi_unstable".parse(), Ok(WasiVersion::Unstable));
+        assert_eq!("Preview1".parse(), Ok(WasiVersion::Snapshot1));
+        assert_eq!(
+            "snapshot2".parse::<WasiVersion>(),
+            Err(UnknownWasiVersion("snapshot2".to_string()))
+        );
+        assert!("".parse::<WasiVersion>().is_err());
     }
 }
@@ -6,26 +6,125 @@
 //!   with wasmer with the expected output
 
 use glob::glob;
+use regex::{NoExpand, Regex};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use std::io;
 use std::io::prelude::*;
 
+use super::compiler::{rustc_command, with_retries, Clang, Compiler, Rustc};
+use super::dashboard::{wasi_imports, write_dashboard, WASI_FUNCTIONS};
+use super::doctests::extract_wasi_doctests;
+use super::error::WasiTestError;
+use super::fs_effects::{FsEffect, FsSnapshot};
+use super::jobs::{self, JobSlots};
+use super::manifest::{
+    apply_size_baselines, read_post_processing_tools, read_size_baselines, write_manifest,
+    ManifestEntry,
+};
+use super::timings::{timings_table, TestTimings, WasmTimings};
 use super::util;
 use super::wasi_version::*;
 
-#[derive(Debug, Clone, PartialEq)]
+/// The directory the tests run in by default: paths in the test options are
+/// relative to it
+pub const DEFAULT_EXECUTE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/wasi");
+
+/// The directory of the tests, next to which the version directories are
+/// generated
+const WASI_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/wasi");
+
+/// Where the sources that failed to compile to Wasm are kept with `keep_temp`,
+/// as the temporary directory is removed when `build` returns
+const KEPT_SOURCES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/failed-sources");
+
+/// What the native program of a test did, which its expected output is made
+/// of. It can be persisted, to be reused while its source doesn't change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NativeOutput {
-    stdout: String,
-    stderr: String,
-    result: i64,
+    /// The program output on stdout
+    pub stdout: String,
+    /// The program output on stderr
+    pub stderr: String,
+    /// The raw output on stdout, when it isn't UTF-8
+    pub stdout_bytes: Option<Vec<u8>>,
+    /// The raw output on stderr, when it isn't UTF-8
+    pub stderr_bytes: Option<Vec<u8>>,
+    /// The program exit code
+    pub result: i64,
+    /// Whether the program terminated abnormally, by panicking or aborting
+    pub trapped: bool,
+    /// The signal that killed the program, which is a trap in Wasm
+    pub signal: Option<i32>,
+    /// The changes the program made to the recorded trees
+    pub fs_effects: Vec<FsEffect>,
+    /// The contents of the `assert_tempdir_file`s, by path
+    pub tempdir_files: Vec<(String, Vec<u8>)>,
+    /// How long the program took to compile
+    pub compile_time: Duration,
+    /// How long the program took to run
+    pub run_time: Duration,
+}
+
+impl NativeOutput {
+    /// What differs between this output and `other`, of the same program, in
+    /// what the test asserts with its `options`
+    fn differences(&self, other: &NativeOutput, options: &WasiOptions) -> Vec<String> {
+        let mut diffs = vec![];
+        if self.stdout != other.stdout || self.stdout_bytes != other.stdout_bytes {
+            diffs.push(format!(
+                "stdout:\n{}",
+                util::diff_lines(&self.stdout, &other.stdout)
+            ));
+        }
+        // the stderr of a trapping program isn't asserted
+        if options.trap.is_none()
+            && (self.stderr != other.stderr || self.stderr_bytes != other.stderr_bytes)
+        {
+            diffs.push(format!(
+                "stderr:\n{}",
+                util::diff_lines(&self.stderr, &other.stderr)
+            ));
+        }
+        if self.result != other.result {
+            diffs.push(format!("exit code:\n-{}\n+{}", self.result, other.result));
+        }
+        if self.fs_effects != other.fs_effects {
+            diffs.push(format!(
+                "filesystem effects:\n-{:?}\n+{:?}",
+                self.fs_effects, other.fs_effects
+            ));
+        }
+        for ((path, contents), (_, other_contents)) in
+            self.tempdir_files.iter().zip(&other.tempdir_files)
+        {
+            if contents != other_contents {
+                diffs.push(format!(
+                    "file `{}`:\n{}",
+                    path,
+                    util::diff_lines(
+                        &String::from_utf8_lossy(contents),
+                        &String::from_utf8_lossy(other_contents)
+                    )
+                ));
+            }
+        }
+        diffs
+    }
 }
 
 /// Compile and execute the test file as native code, saving the results to be
 /// compared against later.
 ///
+/// It's compiled for the host, or for the `target` triple if there's one, in
+/// which case it's `None` if the program can't run on the host.
+///
 /// This function attempts to clean up its output after it executes it.
 fn generate_native_output(
     temp_dir: &Path,
@@ -33,295 +132,3066 @@ fn generate_native_output(
     normalized_name: &str,
     args: &[String],
     options: &WasiOptions,
-) -> io::Result<NativeOutput> {
-    let executable_path = temp_dir.join(normalized_name);
-    println!(
+    build_options: &BuildOptions,
+    target: Option<&str>,
+) -> Result<Option<NativeOutput>, WasiTestError> {
+    let io_error = WasiTestError::io(Path::new(file));
+    let execute_dir = build_options.execute_dir.as_path();
+    let executable_path = match target {
+        Some(target) => temp_dir.join(format!("{}-{}", normalized_name, target)),
+        None => temp_dir.join(normalized_name),
+    };
+    info!(
         "Compiling program {} to native at {}",
         file,
         executable_path.to_string_lossy()
     );
-    let native_out = Command::new("rustc")
-        .arg(file)
-        .arg("-o")
-        .args(args)
-        .arg(&executable_path)
-        .output()
-        .expect("Failed to compile program to native code");
-    util::print_info_on_error(&native_out, "COMPILATION FAILED");
+    let compile_start = Instant::now();
+    let language = Language::of(file).unwrap_or(Language::Rust);
+    let native_run = with_retries(
+        build_options.compile_attempts,
+        &format!("`{}` natively", file),
+        || {
+            compiler(language, build_options).compile_native(
+                Path::new(file),
+                &executable_path,
+                target,
+                options,
+                execute_dir,
+            )
+        },
+    )
+    .map_err(io_error)?;
+    if !native_run.output.status.success() {
+        return Err(WasiTestError::Compilation {
+            file: PathBuf::from(file),
+            target: match target {
+                Some(target) => format!("`{}`", target),
+                None => "native code".to_string(),
+            },
+            command: native_run.command,
+            stderr: String::from_utf8_lossy(&native_run.output.stderr).to_string(),
+        });
+    }
+    let compile_time = compile_start.elapsed();
+    debug!("Compiled `{}` natively in {:.2?}", file, compile_time);
 
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
-        let mut perm = executable_path
-            .metadata()
-            .expect("native executable")
-            .permissions();
+        let mut perm = executable_path.metadata().map_err(io_error)?.permissions();
         perm.set_mode(0o766);
-        println!(
+        debug!(
             "Setting execute permissions on {}",
             executable_path.to_string_lossy()
         );
-        fs::set_permissions(&executable_path, perm)?;
+        fs::set_permissions(&executable_path, perm).map_err(io_error)?;
+    }
+
+    if options.no_native_run {
+        info!("Not running `{}` natively, it has `no_native_run`", file);
+        return Ok(Some(NativeOutput {
+            stdout: String::new(),
+            stderr: String::new(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            result: 0,
+            trapped: false,
+            signal: None,
+            fs_effects: vec![],
+            tempdir_files: vec![],
+            compile_time,
+            run_time: Duration::ZERO,
+        }));
+    }
+    let output = run_native(
+        &executable_path,
+        file,
+        args,
+        options,
+        build_options,
+        target,
+        compile_time,
+    )?;
+    if let (Some(first), true) = (&output, build_options.check_determinism) {
+        debug!("Running `{}` again to check it's deterministic", file);
+        if let Some(second) = run_native(
+            &executable_path,
+            file,
+            args,
+            options,
+            build_options,
+            target,
+            compile_time,
+        )? {
+            let diffs = first.differences(&second, options);
+            if !diffs.is_empty() {
+                return Err(WasiTestError::Nondeterministic {
+                    file: PathBuf::from(file),
+                    diff: diffs.join("\n"),
+                });
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Runs the native program of the test `file` at `executable_path`, compiled
+/// in `compile_time`, in the environment its options give it. It's `None` if
+/// the program of `target` can't run on the host.
+fn run_native(
+    executable_path: &Path,
+    file: &str,
+    args: &[String],
+    options: &WasiOptions,
+    build_options: &BuildOptions,
+    target: Option<&str>,
+    compile_time: Duration,
+) -> Result<Option<NativeOutput>, WasiTestError> {
+    let io_error = WasiTestError::io(Path::new(file));
+    let execute_dir = build_options.execute_dir.as_path();
+    for path in &options.pre_clean {
+        remove_if_exists(&execute_dir.join(path)).map_err(io_error)?;
     }
+    // restores the permissions when dropped, after the program has run
+    let read_only_dirs = options.read_only_host_dirs().collect::<Vec<_>>();
+    if !read_only_dirs.is_empty() {
+        warn_if_permissions_are_bypassed();
+    }
+    let _read_only_trees = read_only_dirs
+        .iter()
+        .map(|dir| ReadOnlyTree::new(&execute_dir.join(dir)))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(io_error)?;
 
-    println!(
+    debug!(
         "Executing native program at {}",
         executable_path.to_string_lossy()
     );
-    // workspace root
-    const EXECUTE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/wasi");
-    let mut native_command = Command::new(&executable_path)
-        .current_dir(EXECUTE_DIR)
+    let program_dir = options.program_dir(execute_dir);
+    // removed when dropped, after their files have been read
+    let _temp_dirs = NativeTempDirs::create(options, &program_dir).map_err(io_error)?;
+    let mut command = Command::new(executable_path);
+    command
+        .args(args)
+        .current_dir(&program_dir)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .unwrap();
+        .stderr(Stdio::piped());
+    if let Some(first_fd) = options.native_close_fds {
+        cloexec_fds_from(first_fd).map_err(io_error)?;
+    }
+    // unlike the `env` vars, which the Wasm program alone gets
+    if let Some(seed) = options.random_seed {
+        command.env(RANDOM_SEED_VAR, seed.to_string());
+    }
+    // kept alive until the program has run, they're deleted on drop
+    let random_temp_dirs = options
+        .random_tempdir
+        .iter()
+        .map(|env_var| {
+            let dir = tempfile::Builder::new().prefix("wasi-test-").tempdir()?;
+            command.env(env_var, dir.path());
+            Ok((env_var, dir))
+        })
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(io_error)?;
+    let fs_before = FsSnapshot::take(execute_dir, &options.record_fs_effects).map_err(io_error)?;
+    let run_start = Instant::now();
+    let mut native_command = match command.spawn() {
+        Ok(child) => child,
+        // the programs of other platforms may not run on this one
+        Err(e) if target.is_some() => {
+            debug!("Can't run `{}`: {}", executable_path.to_string_lossy(), e);
+            return Ok(None);
+        }
+        Err(e) => return Err(io_error(e)),
+    };
 
-    if let Some(stdin_str) = &options.stdin {
-        write!(native_command.stdin.as_ref().unwrap(), "{}", stdin_str).unwrap();
+    if let Some(stdin) = options.stdin_input() {
+        native_command
+            .stdin
+            .as_ref()
+            .unwrap()
+            .write_all(stdin)
+            .map_err(io_error)?;
     }
+    // close stdin so the program sees its end
+    drop(native_command.stdin.take());
 
-    let result = native_command
-        .wait()
-        .expect("Failed to execute native program");
+    // read as the program runs, so it can't block on a full pipe
+    let stdout_reader = read_to_end_in_background(native_command.stdout.take().unwrap());
+    let stderr_reader = read_to_end_in_background(native_command.stderr.take().unwrap());
+
+    let timeout = build_options.native_timeout;
+    let result = wait_with_timeout(&mut native_command, timeout)
+        .map_err(io_error)?
+        .ok_or_else(|| WasiTestError::Execution {
+            file: PathBuf::from(file),
+            message: format!("killed after running for more than {:?}", timeout),
+        })?;
+    let run_time = run_start.elapsed();
+    debug!("Ran `{}` natively in {:.2?}", file, run_time);
+
+    let fs_after = FsSnapshot::take(execute_dir, &options.record_fs_effects).map_err(io_error)?;
+    let fs_effects = fs_after.effects_since(&fs_before);
+    fs_before
+        .restore(execute_dir, &fs_after)
+        .map_err(io_error)?;
+
+    let (mut stdout_str, stdout_bytes) =
+        text_or_bytes(stdout_reader.join().unwrap().map_err(io_error)?);
+    let (mut stderr_str, stderr_bytes) =
+        text_or_bytes(stderr_reader.join().unwrap().map_err(io_error)?);
+    if (stdout_bytes.is_some() || stderr_bytes.is_some())
+        && (options.serialize_output
+            || !options.random_tempdir.is_empty()
+            || !options.scrub.is_empty())
+    {
+        warn!(
+            "the output of `{}` isn't UTF-8, it's asserted as is without `serialize_output`, `random_tempdir` or `scrub` applied",
+            file
+        );
+    }
+    check_created_files(options, execute_dir).map_err(|message| WasiTestError::Execution {
+        file: PathBuf::from(file),
+        message,
+    })?;
+    let tempdir_files =
+        read_tempdir_files(options, &program_dir).map_err(|message| WasiTestError::Execution {
+            file: PathBuf::from(file),
+            message,
+        })?;
 
-    let stdout_str = {
-        let mut stdout = native_command.stdout.unwrap();
-        let mut s = String::new();
-        stdout.read_to_string(&mut s).unwrap();
-        s
-    };
-    let stderr_str = {
-        let mut stderr = native_command.stderr.unwrap();
-        let mut s = String::new();
-        stderr.read_to_string(&mut s).unwrap();
-        s
-    };
     if !result.success() {
-        println!("NATIVE PROGRAM FAILED");
-        println!("stdout:\n{}", stdout_str);
-        eprintln!("stderr:\n{}", stderr_str);
+        warn!(
+            "NATIVE PROGRAM FAILED\nstdout:\n{}\nstderr:\n{}",
+            stdout_str, stderr_str
+        );
+    }
+
+    // the paths are random, so the expectation refers to them by placeholder
+    for (env_var, dir) in &random_temp_dirs {
+        let path = dir.path().to_string_lossy();
+        let placeholder = format!("{{{{{}}}}}", env_var);
+        stdout_str = stdout_str.replace(&*path, &placeholder);
+        stderr_str = stderr_str.replace(&*path, &placeholder);
     }
 
-    let result = result.code().unwrap() as i64;
-    Ok(NativeOutput {
+    if options
+        .normalize_line_endings
+        .unwrap_or(build_options.normalize_line_endings)
+    {
+        stdout_str = stdout_str.replace("\r\n", "\n");
+        stderr_str = stderr_str.replace("\r\n", "\n");
+    }
+
+    let scrubs = options.scrub_regexes().expect("checked by `test_options`");
+    stdout_str = scrub(&stdout_str, &scrubs);
+    stderr_str = scrub(&stderr_str, &scrubs);
+
+    if options.serialize_output {
+        stdout_str = sort_lines(&stdout_str);
+        stderr_str = sort_lines(&stderr_str);
+    }
+
+    let trapped = terminated_abnormally(&result);
+    let signal = terminating_signal(&result);
+    let result = match result.code() {
+        Some(code) => code as i64,
+        // the exit code of a trapping program isn't asserted
+        None if options.trap.is_some() => 0,
+        None => {
+            let killed_by = match signal {
+                Some(signal) => describe_signal(signal),
+                None => "a signal".to_string(),
+            };
+            return Err(WasiTestError::Execution {
+                file: PathBuf::from(file),
+                message: format!(
+                    "killed by {}, add a `trap` directive if that's expected",
+                    killed_by
+                ),
+            });
+        }
+    };
+    Ok(Some(NativeOutput {
         stdout: stdout_str,
         stderr: stderr_str,
+        stdout_bytes,
+        stderr_bytes,
         result,
-    })
+        trapped,
+        signal,
+        fs_effects,
+        tempdir_files,
+        compile_time,
+        run_time,
+    }))
+}
+
+/// The target triple of the host, as `rustc` reports it
+fn host_triple(rustc: Option<&Path>) -> Result<String, WasiTestError> {
+    let mut command = rustc_command(None, rustc);
+    let out = command
+        .arg("-vV")
+        .output()
+        .map_err(WasiTestError::io(Path::new(command.get_program())))?;
+    String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(str::to_string)
+        .ok_or_else(|| WasiTestError::Execution {
+            file: PathBuf::from(command.get_program()),
+            message: "`-vV` doesn't print the host".to_string(),
+        })
+}
+
+/// Checks the files the program run in `execute_dir` is expected to create,
+/// with the length `assert_file_len` gives them, then removes them so that the
+/// next run starts without them. Returns what's wrong otherwise.
+fn check_created_files(options: &WasiOptions, execute_dir: &Path) -> Result<(), String> {
+    for (path, expected_len) in &options.assert_file_len {
+        let path = execute_dir.join(path);
+        let len = fs::metadata(&path)
+            .map_err(|e| format!("expected file `{}`: {}", path.to_string_lossy(), e))?
+            .len();
+        if len != *expected_len {
+            return Err(format!(
+                "file `{}` is {} bytes long instead of {}",
+                path.to_string_lossy(),
+                len,
+                expected_len
+            ));
+        }
+        debug!("Removing file `{}`", path.to_string_lossy());
+        fs::remove_file(&path)
+            .map_err(|e| format!("removing `{}`: {}", path.to_string_lossy(), e))?;
+    }
+    for path in options.expected_files() {
+        let path = execute_dir.join(path);
+        let metadata = fs::metadata(&path)
+            .map_err(|e| format!("expected file `{}`: {}", path.to_string_lossy(), e))?;
+        debug!("Removing `{}`", path.to_string_lossy());
+        if metadata.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .map_err(|e| format!("removing `{}`: {}", path.to_string_lossy(), e))?;
+    }
+    Ok(())
+}
+
+/// The `tempdir`s holding the `assert_tempdir_file`s of the native program,
+/// created empty in the directory it runs in, as nothing maps them natively,
+/// and removed when dropped
+struct NativeTempDirs {
+    dirs: Vec<PathBuf>,
+}
+
+impl NativeTempDirs {
+    fn create(options: &WasiOptions, program_dir: &Path) -> io::Result<Self> {
+        let mut created = NativeTempDirs { dirs: vec![] };
+        for path in &options.assert_tempdir_file {
+            let (dir, _) = options
+                .split_tempdir_path(path)
+                .expect("`test_options` checks the files are in a `tempdir`");
+            // the program's own directory isn't removed, only its files are
+            if dir == "." {
+                continue;
+            }
+            let dir = program_dir.join(dir);
+            if created.dirs.contains(&dir) {
+                continue;
+            }
+            remove_if_exists(&dir)?;
+            debug!("Creating `{}`", dir.to_string_lossy());
+            fs::create_dir_all(&dir)?;
+            created.dirs.push(dir);
+        }
+        Ok(created)
+    }
+}
+
+impl Drop for NativeTempDirs {
+    fn drop(&mut self) {
+        for dir in &self.dirs {
+            if let Err(e) = fs::remove_dir_all(dir) {
+                warn!("Failed to remove `{}`: {}", dir.to_string_lossy(), e);
+            }
+        }
+    }
 }
 
-/// compile the Wasm file for the given version of WASI
+/// Reads the `assert_tempdir_file`s the program run in `program_dir` wrote,
+/// then removes them so that the next run starts without them. Returns what's
+/// wrong otherwise.
+fn read_tempdir_files(
+    options: &WasiOptions,
+    program_dir: &Path,
+) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut files = vec![];
+    for path in &options.assert_tempdir_file {
+        let host_path = program_dir.join(path);
+        let contents = fs::read(&host_path)
+            .map_err(|e| format!("expected file `{}`: {}", host_path.to_string_lossy(), e))?;
+        debug!("Removing file `{}`", host_path.to_string_lossy());
+        fs::remove_file(&host_path)
+            .map_err(|e| format!("removing `{}`: {}", host_path.to_string_lossy(), e))?;
+        files.push((path.clone(), contents));
+    }
+    Ok(files)
+}
+
+/// Runs the Wasm of `test` at `wasm_path` with the `wasmer` binary, in the
+/// same environment as the native program, and checks that its output, exit
+/// code, files and filesystem effects are the ones the test expects.
 ///
-/// returns the path of where the wasm file is
-fn compile_wasm_for_version(
-    temp_dir: &Path,
+/// A test expected to trap only has to fail, as its output isn't asserted.
+fn verify_with_wasmer(
+    wasmer: &Path,
     file: &str,
-    out_dir: &Path,
-    rs_mod_name: &str,
+    wasm_path: &Path,
     version: WasiVersion,
-) -> io::Result<PathBuf> {
-    //let out_dir = base_dir; //base_dir.join("..").join(version.get_directory_name());
-    if !out_dir.exists() {
-        fs::create_dir(&out_dir)?;
+    test: &WasiTest,
+    build_options: &BuildOptions,
+) -> Result<(), WasiTestError> {
+    let options = &test.options;
+    if version.is_component() {
+        // the runner doesn't run components yet
+        info!(
+            "Not verifying `{}` with wasmer, it's a component",
+            wasm_path.to_string_lossy()
+        );
+        return Ok(());
     }
-    let wasm_out_name = {
-        let mut wasm_out_name = out_dir.join(rs_mod_name);
-        wasm_out_name.set_extension("wasm");
-        wasm_out_name
-    };
-    println!("Reading contents from file `{}`", file);
-    let file_contents: String = {
-        let mut fc = String::new();
-        let mut f = fs::OpenOptions::new().read(true).open(&file)?;
-        f.read_to_string(&mut fc)?;
-        fc
+    let io_error = WasiTestError::io(wasmer);
+    let execute_dir = build_options.execute_dir.as_path();
+    let mismatch = |diff: String| WasiTestError::WasmerMismatch {
+        file: PathBuf::from(file),
+        version,
+        diff,
     };
 
-    let temp_wasi_rs_file_name = temp_dir.join(format!("wasi_modified_version_{}.rs", rs_mod_name));
+    // removed when dropped, after the program has run
+    let mut sparse_files = vec![];
+    for sparse_file in &options.sparse_file {
+        let created = CreatedSparseFile::create(sparse_file, execute_dir)
+            .map_err(WasiTestError::io(&execute_dir.join(&sparse_file.path)))?;
+        // the native run would have been skipped otherwise
+        sparse_files.extend(created);
+    }
+    for path in &options.pre_clean {
+        remove_if_exists(&execute_dir.join(path)).map_err(io_error)?;
+    }
+    let _read_only_trees = options
+        .read_only_host_dirs()
+        .map(|dir| ReadOnlyTree::new(&execute_dir.join(dir)))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(io_error)?;
+
+    let mut command = Command::new(wasmer);
+    command.arg("run");
+    for (key, value) in &options.env {
+        command.arg("--env").arg(format!("{}={}", key, value));
+    }
+    for dir in &options.dir {
+        command.arg("--dir").arg(&dir.path);
+    }
+    for (guest, host) in &options.mapdir {
+        command.arg("--mapdir").arg(format!("{}:{}", guest, host));
+    }
+    if let Some(cwd) = &options.cwd {
+        command.arg("--mapdir").arg(format!(".:{}", cwd));
+    }
+    // kept alive until the program has run, they're deleted on drop
+    let temp_dirs = options
+        .tempdir
+        .iter()
+        .map(|_| tempfile::Builder::new().prefix("wasi-test-").tempdir())
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(io_error)?;
+    for (guest, dir) in options.tempdir.iter().zip(&temp_dirs) {
+        command
+            .arg("--mapdir")
+            .arg(format!("{}:{}", guest, dir.path().to_string_lossy()));
+    }
+    // mapped at their host path, so that the output names them as natively
+    let random_temp_dirs = options
+        .random_tempdir
+        .iter()
+        .map(|env_var| {
+            let dir = tempfile::Builder::new().prefix("wasi-test-").tempdir()?;
+            let path = dir.path().to_string_lossy().to_string();
+            command
+                .arg("--mapdir")
+                .arg(format!("{}:{}", path, path))
+                .arg("--env")
+                .arg(format!("{}={}", env_var, path));
+            Ok((env_var, dir))
+        })
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(io_error)?;
+    command
+        .arg(wasm_path)
+        .arg("--")
+        .args(&options.args)
+        .current_dir(execute_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    debug!("Command {:?}", command);
+
+    let fs_before = FsSnapshot::take(execute_dir, &options.record_fs_effects).map_err(io_error)?;
+    let mut child = command.spawn().map_err(io_error)?;
+    if let Some(stdin) = options.stdin_input() {
+        child
+            .stdin
+            .as_ref()
+            .unwrap()
+            .write_all(stdin)
+            .map_err(io_error)?;
+    }
+    drop(child.stdin.take());
+    let stdout_reader = read_to_end_in_background(child.stdout.take().unwrap());
+    let stderr_reader = read_to_end_in_background(child.stderr.take().unwrap());
+    let timeout = build_options.native_timeout;
+    let status = wait_with_timeout(&mut child, timeout)
+        .map_err(io_error)?
+        .ok_or_else(|| mismatch(format!("killed after running for more than {:?}", timeout)))?;
+
+    let fs_after = FsSnapshot::take(execute_dir, &options.record_fs_effects).map_err(io_error)?;
+    let fs_effects = fs_after.effects_since(&fs_before);
+    fs_before
+        .restore(execute_dir, &fs_after)
+        .map_err(io_error)?;
+    drop(sparse_files);
+
+    let (mut stdout, stdout_bytes) =
+        text_or_bytes(stdout_reader.join().unwrap().map_err(io_error)?);
+    let (mut stderr, stderr_bytes) =
+        text_or_bytes(stderr_reader.join().unwrap().map_err(io_error)?);
+    if options.trap.is_some() {
+        if status.success() {
+            return Err(mismatch(
+                "expected to trap, but it exited successfully".to_string(),
+            ));
+        }
+        return Ok(());
+    }
+    check_created_files(options, execute_dir).map_err(mismatch)?;
+    let mut tempdir_files = vec![];
+    for (path, _) in &test.tempdir_files {
+        let (dir, in_dir) = options
+            .split_tempdir_path(path)
+            .expect("the files of a test are in its `tempdir`s");
+        let index = options.tempdir.iter().position(|d| d == dir).unwrap();
+        let host_path = temp_dirs[index].path().join(in_dir);
+        let contents = fs::read(&host_path).map_err(WasiTestError::io(&host_path))?;
+        tempdir_files.push((path.clone(), contents));
+    }
+
+    for (env_var, dir) in &random_temp_dirs {
+        let path = dir.path().to_string_lossy();
+        let placeholder = format!("{{{{{}}}}}", env_var);
+        stdout = stdout.replace(&*path, &placeholder);
+        stderr = stderr.replace(&*path, &placeholder);
+    }
+    if options
+        .normalize_line_endings
+        .unwrap_or(build_options.normalize_line_endings)
     {
-        let mut actual_file = fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(&temp_wasi_rs_file_name)
-            .unwrap();
-        actual_file.write_all(b"#![feature(wasi_ext)]\n").unwrap();
-        actual_file.write_all(file_contents.as_bytes()).unwrap();
+        stdout = stdout.replace("\r\n", "\n");
+        stderr = stderr.replace("\r\n", "\n");
+    }
+    let scrubs = options
+        .scrub_regexes()
+        .expect("the patterns of a test are valid");
+    stdout = scrub(&stdout, &scrubs);
+    stderr = scrub(&stderr, &scrubs);
+    if options.serialize_output {
+        stdout = sort_lines(&stdout);
+        stderr = sort_lines(&stderr);
     }
 
-    println!(
-        "Compiling wasm module `{}` with toolchain `{}`",
-        &wasm_out_name.to_string_lossy(),
-        version.get_compiler_toolchain()
+    let mut diffs = vec![];
+    if !options.stdout_contains.is_empty() {
+        for text in &options.stdout_contains {
+            if !stdout.contains(text.as_str()) {
+                diffs.push(format!("stdout doesn't contain {:?}:\n{}", text, stdout));
+            }
+        }
+    } else if !options.no_native_run && (stdout != test.stdout || stdout_bytes != test.stdout_bytes)
+    {
+        diffs.push(format!(
+            "stdout:\n{}",
+            util::diff_lines(&test.stdout, &stdout)
+        ));
+    }
+    if !options.stderr_contains.is_empty() {
+        for text in &options.stderr_contains {
+            if !stderr.contains(text.as_str()) {
+                diffs.push(format!("stderr doesn't contain {:?}:\n{}", text, stderr));
+            }
+        }
+    } else if !options.no_native_run && (stderr != test.stderr || stderr_bytes != test.stderr_bytes)
+    {
+        diffs.push(format!(
+            "stderr:\n{}",
+            util::diff_lines(&test.stderr, &stderr)
+        ));
+    }
+    let result = status.code().map(i64::from);
+    if result != Some(test.result) {
+        diffs.push(format!(
+            "exit code:\n-{}\n+{}",
+            test.result,
+            result.map_or("none".to_string(), |code| code.to_string())
+        ));
+    }
+    if fs_effects != test.fs_effects {
+        diffs.push(format!(
+            "filesystem effects:\n-{:?}\n+{:?}",
+            test.fs_effects, fs_effects
+        ));
+    }
+    for ((path, expected), (_, contents)) in test.tempdir_files.iter().zip(&tempdir_files) {
+        if contents != expected {
+            diffs.push(format!(
+                "file `{}`:\n{}",
+                path,
+                util::diff_lines(
+                    &String::from_utf8_lossy(expected),
+                    &String::from_utf8_lossy(contents)
+                )
+            ));
+        }
+    }
+    if !diffs.is_empty() {
+        return Err(mismatch(diffs.join("\n")));
+    }
+    debug!(
+        "`{}` runs like the native program",
+        wasm_path.to_string_lossy()
     );
-    let mut command = Command::new("rustc");
+    Ok(())
+}
 
-    command
-        .arg(format!("+{}", version.get_compiler_toolchain()))
-        .arg("--target=wasm32-wasi")
-        .arg("-C")
-        .arg("opt-level=z")
-        .arg(&temp_wasi_rs_file_name)
-        .arg("-o")
-        .arg(&wasm_out_name);
-    println!("Command {:?}", command);
+/// The env var giving a test its `seed`, natively and in Wasm, for the tests
+/// whose output depends on randomness to seed their generator with
+pub const RANDOM_SEED_VAR: &str = "WASI_TEST_RANDOM_SEED";
 
-    let wasm_compilation_out = command.output().expect("Failed to compile program to wasm");
-    util::print_info_on_error(&wasm_compilation_out, "WASM COMPILATION");
-    println!(
-        "Removing file `{}`",
-        &temp_wasi_rs_file_name.to_string_lossy()
-    );
+/// What the matches of the `scrub` patterns are replaced with
+pub const SCRUB_PLACEHOLDER: &str = "[scrubbed]";
 
-    // to prevent commiting huge binary blobs forever
-    let wasm_strip_out = Command::new("wasm-strip")
-        .arg(&wasm_out_name)
-        .output()
-        .expect("Failed to strip compiled wasm module");
-    util::print_info_on_error(&wasm_strip_out, "STRIPPING WASM");
-    let wasm_opt_out = Command::new("wasm-opt")
-        .arg("-Oz")
-        .arg(&wasm_out_name)
-        .arg("-o")
-        .arg(&wasm_out_name)
-        .output()
-        .expect("Failed to optimize compiled wasm module with wasm-opt!");
-    util::print_info_on_error(&wasm_opt_out, "OPTIMIZING WASM");
+/// `output` with the matches of `scrubs` replaced with `SCRUB_PLACEHOLDER`
+fn scrub(output: &str, scrubs: &[Regex]) -> String {
+    scrubs.iter().fold(output.to_string(), |output, scrub| {
+        scrub
+            .replace_all(&output, NoExpand(SCRUB_PLACEHOLDER))
+            .into_owned()
+    })
+}
+
+/// The exit code of a Rust program that panicked
+const PANIC_EXIT_CODE: i32 = 101;
 
-    Ok(wasm_out_name)
+/// Whether the program panicked or was killed by a signal, as by
+/// `std::process::abort` or a segfault. Both are traps in Wasm.
+fn terminated_abnormally(status: &ExitStatus) -> bool {
+    terminating_signal(status).is_some() || status.code() == Some(PANIC_EXIT_CODE)
 }
 
-/// Returns the a Vec of the test modules created
-fn compile(temp_dir: &Path, file: &str, wasi_versions: &[WasiVersion]) {
-    let src_code: String = fs::read_to_string(file).unwrap();
-    let options: WasiOptions = extract_args_from_source_file(&src_code).unwrap_or_default();
+/// The signal that killed the program, if one did
+#[cfg(unix)]
+fn terminating_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
 
-    assert!(file.ends_with(".rs"));
-    let rs_mod_name = {
-        Path::new(&file.to_lowercase())
-            .file_stem()
-            .unwrap()
-            .to_string_lossy()
-            .to_string()
-    };
-    let base_dir = Path::new(file).parent().unwrap();
-    let NativeOutput {
-        stdout,
-        stderr,
-        result,
-    } = generate_native_output(temp_dir, &file, &rs_mod_name, &options.args, &options)
-        .expect("Generate native output");
+#[cfg(not(unix))]
+fn terminating_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
 
-    let test = WasiTest {
-        wasm_prog_name: format!("{}.wasm", rs_mod_name),
-        stdout,
-        stderr,
-        result,
-        options,
+/// The number of `signal`, with its name if it's one a test could die of
+#[cfg(unix)]
+fn describe_signal(signal: i32) -> String {
+    let name = match signal {
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGILL => "SIGILL",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGTRAP => "SIGTRAP",
+        _ => return format!("signal {}", signal),
     };
-    let test_serialized = test.into_wasi_wast();
-    println!("Generated test output: {}", &test_serialized);
+    format!("signal {} ({})", signal, name)
+}
 
-    wasi_versions
-        .into_iter()
-        .map(|&version| {
-            let out_dir = base_dir.join("..").join(version.get_directory_name());
-            if !out_dir.exists() {
-                fs::create_dir(&out_dir).unwrap();
-            }
-            let wasm_out_name = {
-                let mut wasm_out_name = out_dir.join(rs_mod_name.clone());
-                wasm_out_name.set_extension("wast");
-                wasm_out_name
-            };
-            println!("Writing test output to {}", wasm_out_name.to_string_lossy());
-            fs::write(&wasm_out_name, test_serialized.clone()).unwrap();
+#[cfg(not(unix))]
+fn describe_signal(signal: i32) -> String {
+    format!("signal {}", signal)
+}
 
-            println!("Compiling wasm version {:?}", version);
-            compile_wasm_for_version(temp_dir, file, &out_dir, &rs_mod_name, version)
-                .expect(&format!("Could not compile Wasm to WASI version {:?}, perhaps you need to install the `{}` rust toolchain", version, version.get_compiler_toolchain()));
-        }).for_each(drop); // Do nothing with it, but let the iterator be consumed/iterated.
+fn read_to_end_in_background<R: Read + Send + 'static>(
+    mut reader: R,
+) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    })
 }
 
-const WASI_TEST_SRC_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/wasi/tests/*.rs");
-pub fn build(wasi_versions: &[WasiVersion]) {
-    let temp_dir = tempfile::TempDir::new().unwrap();
-    for entry in glob(WASI_TEST_SRC_DIR).unwrap() {
-        match entry {
-            Ok(path) => {
-                let test = path.to_str().unwrap();
-                compile(temp_dir.path(), test, wasi_versions);
-            }
-            Err(e) => println!("{:?}", e),
+/// Returns the output as text, with the bytes themselves if they aren't UTF-8,
+/// in which case the text is lossy.
+fn text_or_bytes(bytes: Vec<u8>) -> (String, Option<Vec<u8>>) {
+    match String::from_utf8(bytes) {
+        Ok(text) => (text, None),
+        Err(e) => (
+            String::from_utf8_lossy(e.as_bytes()).to_string(),
+            Some(e.into_bytes()),
+        ),
+    }
+}
+
+/// Waits for `child` to exit, killing it if it's still running after
+/// `timeout`, in which case `None` is returned.
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
         }
+        thread::sleep(Duration::from_millis(10));
     }
-    println!("All modules generated.");
 }
 
-/// This is the structure of the `.wast` file
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct WasiTest {
-    /// The name of the wasm module to run
-    pub wasm_prog_name: String,
-    /// The program expected output on stdout
-    pub stdout: String,
-    /// The program expected output on stderr
-    pub stderr: String,
-    /// The program expected result
-    pub result: i64,
-    /// The program options
-    pub options: WasiOptions,
+/// Sorts the lines of `output`, for programs whose lines are deterministic but
+/// not their order (like threads each printing their own).
+///
+/// Only whole lines are reordered: a line split by writes from another thread
+/// isn't put back together.
+fn sort_lines(output: &str) -> String {
+    let mut lines = output.split_inclusive('\n').collect::<Vec<&str>>();
+    lines.sort_unstable();
+    lines.concat()
 }
 
-impl WasiTest {
-    fn into_wasi_wast(&self) -> String {
-        let mut out = format!(
-            ";; This file was generated by https://github.com/wasmerio/wasi-tests\n
-(wasi_test \"{}\"",
-            self.wasm_prog_name
+/// Removes the file or directory at `path`, doing nothing if there is none.
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => {
+            debug!("Removed `{}`", path.to_string_lossy());
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A sparse file created for a test, removed when dropped
+struct CreatedSparseFile {
+    path: PathBuf,
+}
+
+impl CreatedSparseFile {
+    /// Creates the sparse file, relative to `execute_dir`, or returns `None`
+    /// without leaving anything behind if the platform or filesystem can't
+    /// make it sparse.
+    fn create(sparse_file: &SparseFile, execute_dir: &Path) -> io::Result<Option<Self>> {
+        let path = execute_dir.join(&sparse_file.path);
+        debug!(
+            "Creating sparse file `{}` with a marker at offset {}",
+            path.to_string_lossy(),
+            sparse_file.offset
         );
-        if !self.options.env.is_empty() {
-            let envs = self
-                .options
-                .env
-                .iter()
-                .map(|(name, value)| format!("\"{}={}\"", name, value))
-                .collect::<Vec<String>>()
-                .join(" ");
-            out += &format!("\n  (envs {})", envs);
+        let mut file = fs::File::create(&path)?;
+        let created = CreatedSparseFile { path };
+        file.set_len(sparse_file.offset)?;
+        file.seek(io::SeekFrom::Start(sparse_file.offset))?;
+        file.write_all(sparse_file.marker.as_bytes())?;
+        file.sync_all()?;
+
+        if is_sparse(&file.metadata()?) {
+            Ok(Some(created))
+        } else {
+            Ok(None)
         }
-        if !self.options.args.is_empty() {
-            let args = self
-                .options
-                .args
-                .iter()
-                .map(|v| format!("\"{}\"", v))
-                .collect::<Vec<String>>()
-                .join(" ");
-            out += &format!("\n  (args {})", args);
+    }
+}
+
+impl Drop for CreatedSparseFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(
+                "Failed to remove sparse file `{}`: {}",
+                self.path.to_string_lossy(),
+                e
+            );
         }
+    }
+}
 
-        if !self.options.dir.is_empty() {
-            let preopens = self
-                .options
-                .dir
-                .iter()
-                .map(|v| format!("\"{}\"", v))
-                .collect::<Vec<String>>()
-                .join(" ");
-            out += &format!("\n  (preopens {})", preopens);
+#[cfg(unix)]
+fn is_sparse(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    // `blocks` counts the 512-byte blocks actually allocated
+    metadata.blocks() * 512 < metadata.len()
+}
+
+#[cfg(not(unix))]
+fn is_sparse(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// A file tree made read-only, with its permissions restored when dropped
+struct ReadOnlyTree {
+    original_permissions: Vec<(PathBuf, fs::Permissions)>,
+}
+
+impl ReadOnlyTree {
+    fn new(root: &Path) -> io::Result<Self> {
+        debug!("Making `{}` read-only", root.to_string_lossy());
+        let mut tree = ReadOnlyTree {
+            original_permissions: vec![],
+        };
+        tree.make_read_only(root)?;
+        Ok(tree)
+    }
+
+    fn make_read_only(&mut self, path: &Path) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.file_type().is_symlink() {
+            return Ok(());
         }
-        if !self.options.mapdir.is_empty() {
-            let map_dirs = self
-                .options
-                .mapdir
-                .iter()
-                .map(|(a, b)| format!("\"{}:{}\"", a, b))
-                .collect::<Vec<String>>()
-                .join(" ");
-            out += &format!("\n  (map_dirs {})", map_dirs);
+        if metadata.is_dir() {
+            for entry in fs::read_dir(path)? {
+                self.make_read_only(&entry?.path())?;
+            }
+        }
+        let mut permissions = metadata.permissions();
+        self.original_permissions
+            .push((path.to_path_buf(), permissions.clone()));
+        permissions.set_readonly(true);
+        fs::set_permissions(path, permissions)
+    }
+}
+
+impl Drop for ReadOnlyTree {
+    fn drop(&mut self) {
+        for (path, permissions) in self.original_permissions.drain(..).rev() {
+            if let Err(e) = fs::set_permissions(&path, permissions) {
+                warn!(
+                    "Failed to restore the permissions of `{}`: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn warn_if_permissions_are_bypassed() {
+    if unsafe { libc::geteuid() } == 0 {
+        warn!("running as root, read-only permissions don't apply to the native program");
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_permissions_are_bypassed() {}
+
+/// Marks every fd from `first_fd` up close-on-exec, so the native program
+/// can't rely on descriptors inherited from the generator (like cargo's
+/// jobserver pipes) that won't exist under WASI. It's done before spawning
+/// rather than by closing them in the child, which would be a syscall per
+/// possible fd and would close the pipe std reports a failed `exec` through.
+#[cfg(unix)]
+fn cloexec_fds_from(first_fd: u32) -> io::Result<()> {
+    let fd_dir = if cfg!(target_os = "linux") {
+        "/proc/self/fd"
+    } else {
+        "/dev/fd"
+    };
+    // collected first, as listing the directory opens an fd of its own
+    let fds = fs::read_dir(fd_dir)?
+        .filter_map(|entry| {
+            entry
+                .ok()?
+                .file_name()
+                .to_str()?
+                .parse::<libc::c_int>()
+                .ok()
+        })
+        .filter(|&fd| fd >= first_fd as libc::c_int)
+        .collect::<Vec<_>>();
+    for fd in fds {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        // the fd of the listing, closed by now
+        if flags == -1 {
+            continue;
+        }
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn cloexec_fds_from(_first_fd: u32) -> io::Result<()> {
+    warn!("`native_close_fds` is only supported on Unix, inherited fds are kept");
+    Ok(())
+}
+
+/// compile the Wasm file for the given version of WASI, writing it to `out_dir`
+///
+/// returns the path of where the wasm file is, and how long building it took
+fn compile_wasm_for_version(
+    temp_dir: &Path,
+    file: &str,
+    out_dir: &Path,
+    rs_mod_name: &str,
+    version: WasiVersion,
+    options: &WasiOptions,
+    build_options: &BuildOptions,
+) -> Result<(PathBuf, WasmTimings), WasiTestError> {
+    if !out_dir.exists() {
+        fs::create_dir_all(out_dir).map_err(WasiTestError::io(out_dir))?;
+    }
+    let wasm_out_name = {
+        let mut wasm_out_name = out_dir.join(rs_mod_name);
+        wasm_out_name.set_extension("wasm");
+        wasm_out_name
+    };
+    let (temp_wasm_file_name, mut timings) =
+        build_wasm(temp_dir, file, rs_mod_name, version, options, build_options)?;
+
+    let write_start = Instant::now();
+    let wasm = fs::read(&temp_wasm_file_name).map_err(WasiTestError::io(&temp_wasm_file_name))?;
+    util::write_atomically(&wasm_out_name, wasm).map_err(WasiTestError::io(&wasm_out_name))?;
+    if build_options.emit_wat {
+        write_wat(&wasm_out_name, version)?;
+    }
+    timings.post_process += write_start.elapsed();
+    debug!("Wrote `{}`", wasm_out_name.to_string_lossy());
+
+    Ok((wasm_out_name, timings))
+}
+
+/// Writes the source of a test, `file_contents`, as it's compiled to Wasm for
+/// `version`, with the features the version requires, to `temp_dir`.
+///
+/// returns the path of the source, and the one of the module to build
+fn write_wasm_source(
+    temp_dir: &Path,
+    file_contents: &str,
+    rs_mod_name: &str,
+    language: Language,
+    version: WasiVersion,
+) -> Result<(PathBuf, PathBuf), WasiTestError> {
+    // one per version, as the versions are compiled in parallel in the
+    // test's own temp dir
+    let temp_wasm_file_name = temp_dir.join(format!(
+        "{}_{}.wasm",
+        rs_mod_name,
+        version.get_directory_name()
+    ));
+    let temp_wasi_rs_file_name = temp_dir.join(format!(
+        "wasi_modified_version_{}_{}.{}",
+        rs_mod_name,
+        version.get_directory_name(),
+        language.extension()
+    ));
+    let file_contents = match language {
+        Language::Rust => with_features(file_contents, version.get_required_features()),
+        Language::C => file_contents.to_string(),
+    };
+    {
+        let mut actual_file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&temp_wasi_rs_file_name)
+            .map_err(WasiTestError::io(&temp_wasi_rs_file_name))?;
+        actual_file
+            .write_all(file_contents.as_bytes())
+            .map_err(WasiTestError::io(&temp_wasi_rs_file_name))?;
+    }
+    Ok((temp_wasi_rs_file_name, temp_wasm_file_name))
+}
+
+/// Builds the Wasm module of `file` for `version` in `temp_dir`, and
+/// post-processes and validates it. The module is complete once it returns.
+///
+/// returns the path of the module in `temp_dir`, and how long building it took
+fn build_wasm(
+    temp_dir: &Path,
+    file: &str,
+    rs_mod_name: &str,
+    version: WasiVersion,
+    options: &WasiOptions,
+    build_options: &BuildOptions,
+) -> Result<(PathBuf, WasmTimings), WasiTestError> {
+    let io_error = WasiTestError::io(Path::new(file));
+    debug!("Reading contents from file `{}`", file);
+    let file_contents: String = {
+        let mut fc = String::new();
+        let mut f = fs::OpenOptions::new()
+            .read(true)
+            .open(file)
+            .map_err(io_error)?;
+        f.read_to_string(&mut fc).map_err(io_error)?;
+        fc
+    };
+
+    let language = Language::of(file).unwrap_or(Language::Rust);
+    let (temp_wasi_rs_file_name, temp_wasm_file_name) =
+        write_wasm_source(temp_dir, &file_contents, rs_mod_name, language, version)?;
+
+    info!(
+        "Compiling `{}` for WASI version {:?} with `{}`",
+        file,
+        version,
+        compiler_name(language, version, build_options)
+    );
+
+    let compile_start = Instant::now();
+    let wasm_compilation = with_retries(
+        build_options.compile_attempts,
+        &format!("`{}` for WASI version {:?}", file, version),
+        || {
+            compiler(language, build_options).compile_wasm(
+                &temp_wasi_rs_file_name,
+                &temp_wasm_file_name,
+                version,
+                options,
+                &build_options.execute_dir,
+            )
+        },
+    )
+    .map_err(io_error)?;
+    if !wasm_compilation.output.status.success() {
+        let stderr = String::from_utf8_lossy(&wasm_compilation.output.stderr);
+        if is_toolchain_missing(&stderr) {
+            return Err(WasiTestError::ToolchainMissing {
+                version,
+                toolchain: version.get_compiler_toolchain().to_string(),
+            });
+        }
+        if build_options.keep_temp {
+            keep_source(&temp_wasi_rs_file_name);
+        }
+        return Err(WasiTestError::Compilation {
+            file: PathBuf::from(file),
+            target: format!("WASI version {:?}", version),
+            command: wasm_compilation.command,
+            stderr: stderr.to_string(),
+        });
+    }
+    let compile_time = compile_start.elapsed();
+    debug!(
+        "Compiled `{}` for WASI version {:?} in {:.2?}",
+        file, version, compile_time
+    );
+    debug!(
+        "Removing file `{}`",
+        &temp_wasi_rs_file_name.to_string_lossy()
+    );
+
+    let post_process_start = Instant::now();
+    if !build_options.skip_post_processing {
+        post_process_wasm(&temp_wasm_file_name, version)?;
+    }
+
+    let wasm = fs::read(&temp_wasm_file_name).map_err(WasiTestError::io(&temp_wasm_file_name))?;
+    // after the post-processing, which is the most likely to break it
+    if build_options.validate_wasm {
+        debug!("Validating `{}`", temp_wasm_file_name.to_string_lossy());
+        wasmparser::validate(&wasm).map_err(|e| WasiTestError::InvalidWasm {
+            file: PathBuf::from(file),
+            version,
+            message: e.to_string(),
+        })?;
+    }
+    if !options.imports.is_empty() && !version.is_component() {
+        let unexpected = wasi_imports(&wasm)
+            .map_err(WasiTestError::io(&temp_wasm_file_name))?
+            .into_iter()
+            .filter(|import| !options.imports.contains(import))
+            .collect::<Vec<String>>();
+        if !unexpected.is_empty() {
+            return Err(WasiTestError::UnexpectedImports {
+                file: PathBuf::from(file),
+                version,
+                imports: unexpected,
+            });
+        }
+    }
+    let post_process_time = post_process_start.elapsed();
+    debug!(
+        "Post-processed and validated `{}` in {:.2?}",
+        temp_wasm_file_name.to_string_lossy(),
+        post_process_time
+    );
+
+    Ok((
+        temp_wasm_file_name,
+        WasmTimings {
+            compile: compile_time,
+            post_process: post_process_time,
+        },
+    ))
+}
+
+/// Declares the `features` that `source` doesn't declare yet, in its first
+/// `#![feature(...)]` attribute if it has one and in a new one at its start,
+/// after its shebang if it has one, otherwise. Features declared by a
+/// `#![cfg_attr(..., feature(...))]` count as declared, as it's how a test
+/// only declares them for WASI. Only attributes on a single line are seen.
+fn with_features(source: &str, features: &[&str]) -> String {
+    let inner_attributes = source
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("#!["))
+        .collect::<Vec<&str>>();
+    let declared = inner_attributes
+        .iter()
+        .filter_map(|attribute| {
+            let list = &attribute[attribute.find("feature(")? + "feature(".len()..];
+            Some(list[..list.find(')')?].split(','))
+        })
+        .flatten()
+        .map(str::trim)
+        .collect::<Vec<&str>>();
+    let missing = features
+        .iter()
+        .filter(|feature| !declared.contains(feature))
+        .copied()
+        .collect::<Vec<&str>>();
+    if missing.is_empty() {
+        return source.to_string();
+    }
+
+    // merged, so that the lines of the compilation errors are the source's
+    if let Some(attribute) = inner_attributes
+        .iter()
+        .find(|attribute| attribute.starts_with("#![feature(") && attribute.ends_with(")]"))
+    {
+        let list = attribute["#![feature(".len()..attribute.len() - ")]".len()].trim_end();
+        let separator = match list.chars().last() {
+            None => "",
+            Some(',') => " ",
+            Some(_) => ", ",
+        };
+        let merged = format!("#![feature({}{}{})]", list, separator, missing.join(", "));
+        return source.replacen(attribute, &merged, 1);
+    }
+    let attribute = format!("#![feature({})]\n", missing.join(", "));
+    // after the shebang, which must stay first
+    match source.split_once('\n') {
+        Some((shebang, rest)) if shebang.starts_with("#!") && !shebang.starts_with("#![") => {
+            format!("{}\n{}{}", shebang, attribute, rest)
+        }
+        _ => attribute + source,
+    }
+}
+
+/// Copies the source at `path`, which failed to compile, to `KEPT_SOURCES_DIR`
+/// and logs where it is. Failing to is only warned about, as the compilation
+/// error is what matters.
+fn keep_source(path: &Path) {
+    let kept_path = Path::new(KEPT_SOURCES_DIR).join(path.file_name().unwrap());
+    match fs::create_dir_all(KEPT_SOURCES_DIR).and_then(|()| fs::copy(path, &kept_path)) {
+        Ok(_) => info!(
+            "The source that failed to compile is kept at `{}`",
+            kept_path.to_string_lossy()
+        ),
+        Err(e) => warn!(
+            "Failed to keep the source that failed to compile, `{}`: {}",
+            path.to_string_lossy(),
+            e
+        ),
+    }
+}
+
+/// Shrinks the Wasm at `wasm_path` with `wasm-strip` and `wasm-opt`
+fn post_process_wasm(wasm_path: &Path, version: WasiVersion) -> Result<(), WasiTestError> {
+    if version.is_component() {
+        // wasm-strip and wasm-opt only handle core modules
+        info!(
+            "Not post-processing `{}`, it's a component",
+            wasm_path.to_string_lossy()
+        );
+        return Ok(());
+    }
+    // to prevent commiting huge binary blobs forever
+    run_post_processing_tool(Command::new("wasm-strip").arg(wasm_path), "STRIPPING WASM")
+        .map_err(WasiTestError::io(wasm_path))?;
+    run_post_processing_tool(
+        Command::new("wasm-opt")
+            .arg("-Oz")
+            .arg(wasm_path)
+            .arg("-o")
+            .arg(wasm_path),
+        "OPTIMIZING WASM",
+    )
+    .map_err(WasiTestError::io(wasm_path))
+}
+
+/// The version of each post-processing tool that's installed, by name: the
+/// first line its `--version` prints
+fn post_processing_tool_versions() -> BTreeMap<String, String> {
+    ["wasm-strip", "wasm-opt"]
+        .iter()
+        .filter_map(|&tool| {
+            let out = Command::new(tool).arg("--version").output().ok()?;
+            let version = String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .next()?
+                .trim()
+                .to_string();
+            debug!("Post-processing with {}", version);
+            Some((tool.to_string(), version))
+        })
+        .collect()
+}
+
+/// Warns about the post-processing `tools` whose version doesn't contain the
+/// one it's `pinned` to, as they may change every module
+fn check_pinned_tools(tools: &BTreeMap<String, String>, pinned: &[(String, String)]) {
+    for (tool, pinned_version) in pinned {
+        match tools.get(tool) {
+            Some(version) if version.contains(pinned_version.as_str()) => (),
+            Some(version) => warn!(
+                "`{}` is `{}` rather than the pinned `{}`, the modules it post-processes may change",
+                tool, version, pinned_version
+            ),
+            None => warn!("`{}` is pinned to `{}` but isn't installed", tool, pinned_version),
+        }
+    }
+}
+
+/// Runs a tool shrinking the Wasm module in place. It's skipped with a warning
+/// if it isn't installed, as the module is valid without it, just larger.
+fn run_post_processing_tool(command: &mut Command, context: &str) -> io::Result<()> {
+    match command.output() {
+        Ok(out) => {
+            util::print_info_on_error(&out, context);
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            warn!(
+                "`{}` isn't installed, the Wasm module is left as is",
+                command.get_program().to_string_lossy()
+            );
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The diff of the `.wast` at `wast_path` with the `wast` just generated, or
+/// `None` if they're the same. A missing `.wast` differs from any.
+fn wast_diff(wast_path: &Path, wast: &str) -> Result<Option<String>, WasiTestError> {
+    let written = match fs::read_to_string(wast_path) {
+        Ok(written) => written,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(WasiTestError::io(wast_path)(e)),
+    };
+    // the compilers vary between machines, the output usually doesn't
+    let (written, wast) = (without_meta(&written), without_meta(wast));
+    Ok((written != wast).then(|| util::diff_lines(&written, &wast)))
+}
+
+/// `wast` without its `(meta ...)` clause
+fn without_meta(wast: &str) -> String {
+    wast.split_inclusive('\n')
+        .filter(|line| !line.starts_with("  (meta "))
+        .collect()
+}
+
+/// Writes the filesystem effects `test` records, if it records some, to
+/// `out_dir`
+fn write_fs_effects(out_dir: &Path, test: &WasiTest) -> Result<(), WasiTestError> {
+    if test.options.record_fs_effects.is_empty() {
+        return Ok(());
+    }
+    let fs_effects_path = out_dir.join(test.fs_effects_file_name());
+    info!(
+        "Writing filesystem effects to {}",
+        fs_effects_path.to_string_lossy()
+    );
+    serde_json::to_string_pretty(&test.fs_effects)
+        .map_err(io::Error::from)
+        .and_then(|json| fs::write(&fs_effects_path, json))
+        .map_err(WasiTestError::io(&fs_effects_path))
+}
+
+/// Writes the text format of the Wasm at `wasm_path` to a `.wat` next to it,
+/// with `wasm2wat`. It's skipped with a warning if it isn't installed, as it's
+/// only there to read the module.
+fn write_wat(wasm_path: &Path, version: WasiVersion) -> Result<(), WasiTestError> {
+    if version.is_component() {
+        // wasm2wat only handles core modules
+        info!(
+            "Not writing the text format of `{}`, it's a component",
+            wasm_path.to_string_lossy()
+        );
+        return Ok(());
+    }
+    let wat_path = wasm_path.with_extension("wat");
+    debug!("Writing `{}`", wat_path.to_string_lossy());
+    match Command::new("wasm2wat")
+        .arg(wasm_path)
+        .arg("-o")
+        .arg(&wat_path)
+        .output()
+    {
+        Ok(out) => {
+            util::print_info_on_error(&out, "WRITING WAT");
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            warn!("`wasm2wat` isn't installed, no `.wat` is written");
+            Ok(())
+        }
+        Err(e) => Err(WasiTestError::io(wasm_path)(e)),
+    }
+}
+
+/// Returns the test created, or `None` if it was skipped
+///
+/// The generated files are written to `rel_dir` in the version directories next
+/// to `base_dir`. The Wasm for each version is compiled in parallel, after the
+/// native run.
+fn compile(
+    temp_dir: &Path,
+    file: &str,
+    base_dir: &Path,
+    rel_dir: &Path,
+    wasi_versions: &[WasiVersion],
+    build_options: &BuildOptions,
+    defaults: &TestDefaults,
+) -> Result<Option<(WasiTest, TestTimings)>, WasiTestError> {
+    let src_code: String = fs::read_to_string(file).map_err(WasiTestError::io(Path::new(file)))?;
+    let sidecar = read_sidecar(file)?;
+    let options = test_options(
+        file,
+        &src_code,
+        sidecar.as_deref(),
+        defaults,
+        &base_dir.join(rel_dir),
+        &build_options.execute_dir,
+    )?;
+    // the options files are part of what the test is generated from
+    let mut hash_input = src_code.clone();
+    for options_file in sidecar.iter().chain(&defaults.toml) {
+        hash_input.push('\0');
+        hash_input.push_str(options_file);
+    }
+    if !build_options.native_targets.is_empty() {
+        hash_input.push('\0');
+        hash_input.push_str(&build_options.native_targets.join(" "));
+    }
+    hash_input.push('\0');
+    hash_input.push_str(&build_options.output_settings());
+    let input_files = std::iter::once(&options)
+        .chain(options.by_version.values())
+        .flat_map(|options| options.envfile.iter().chain(&options.arg_file));
+    for input_file in input_files {
+        let path = base_dir.join(rel_dir).join(input_file);
+        hash_input.push('\0');
+        hash_input.push_str(&fs::read_to_string(&path).map_err(WasiTestError::io(&path))?);
+    }
+    // so is the host environment of the `passenv` vars
+    for options in std::iter::once(&options).chain(options.by_version.values()) {
+        for name in &options.passenv {
+            if let Some((_, value)) = options.env.iter().find(|(key, _)| key == name) {
+                hash_input.push('\0');
+                hash_input.push_str(value);
+            }
+        }
+    }
+    if let Some(reason) = &options.skip {
+        if reason.is_empty() {
+            info!("Skipping `{}`", file);
+        } else {
+            info!("Skipping `{}`: {}", file, reason);
+        }
+        return Ok(None);
+    }
+
+    let language = Language::of(file).ok_or_else(|| WasiTestError::Parse {
+        file: PathBuf::from(file),
+        message: "only `.rs` and `.c` files are tests".to_string(),
+    })?;
+    let rs_mod_name = module_name(file);
+    let output_root = build_options.output_root.as_deref();
+    let wasi_versions = generated_versions(file, language, &options, wasi_versions);
+    let is_fresh = |version: WasiVersion| {
+        !build_options.force
+            && is_up_to_date(
+                version_out_dir(base_dir, rel_dir, version, output_root),
+                &rs_mod_name,
+                &hash_input,
+                &compiler_name(language, version, build_options),
+            )
+    };
+    // a dry run or a check is for seeing the output of the test, even if it's
+    // up to date, and an update for comparing it with the `.wast`
+    let wasi_versions = if build_options.dry_run || build_options.check || build_options.update {
+        wasi_versions
+    } else {
+        wasi_versions
+            .into_iter()
+            .filter(|&version| !is_fresh(version))
+            .collect()
+    };
+    if wasi_versions.is_empty() {
+        info!("`{}` is up to date, skipping it", file);
+        return Ok(None);
+    }
+
+    let (test, native_compile_time, native_run_time) = match native_test(
+        temp_dir,
+        file,
+        &rs_mod_name,
+        language,
+        options,
+        build_options,
+    )? {
+        Some(native) => native,
+        None => return Ok(None),
+    };
+    let test_serialized = test.into_wasi_wast();
+    debug!("Generated test output: {}", &test_serialized);
+    let mut timings = TestTimings {
+        native_compile: native_compile_time,
+        native_run: native_run_time,
+        wasm: vec![],
+    };
+
+    let wast_path = |version: WasiVersion| {
+        version_out_dir(base_dir, rel_dir, version, output_root)
+            .join(format!("{}.wast", rs_mod_name))
+    };
+    // with the compiler of the version
+    let version_wast = |version: WasiVersion| {
+        let meta = WasiTestMeta {
+            wasm_compiler: compiler(language, build_options).version(Some(version)),
+            ..test.meta.clone().unwrap_or_default()
+        };
+        test.for_version(version)
+            .to_wast(version.is_component(), Some(&meta))
+    };
+
+    if build_options.dry_run {
+        for &version in &wasi_versions {
+            // on stdout, so that it can be redirected apart from the log
+            println!(
+                ";; {} (dry run, not written)\n{}",
+                wast_path(version).to_string_lossy(),
+                version_wast(version)
+            );
+        }
+        return Ok(Some((test, timings)));
+    }
+
+    if build_options.check {
+        let mut out_of_sync = vec![];
+        for &version in &wasi_versions {
+            let wast_path = wast_path(version);
+            if let Some(diff) = wast_diff(&wast_path, &version_wast(version))? {
+                out_of_sync.push((wast_path, diff));
+            }
+        }
+        if !out_of_sync.is_empty() {
+            return Err(WasiTestError::OutOfSync {
+                file: PathBuf::from(file),
+                wasts: out_of_sync,
+            });
+        }
+        debug!("The `.wast`s of `{}` are in sync", file);
+        return Ok(Some((test, timings)));
+    }
+
+    let mut wasi_versions = wasi_versions;
+    if build_options.update {
+        // the Wasm of the versions that are up to date is the same, only their
+        // expected output may have changed
+        let (fresh, stale): (Vec<WasiVersion>, Vec<WasiVersion>) = wasi_versions
+            .into_iter()
+            .partition(|&version| is_fresh(version));
+        for version in fresh {
+            let wast_path = wast_path(version);
+            let wast = version_wast(version);
+            match wast_diff(&wast_path, &wast)? {
+                Some(diff) => {
+                    info!("Updating `{}`:\n{}", wast_path.to_string_lossy(), diff);
+                    util::write_atomically(&wast_path, wast)
+                        .map_err(WasiTestError::io(&wast_path))?;
+                    write_fs_effects(
+                        &version_out_dir(base_dir, rel_dir, version, output_root),
+                        &test,
+                    )?;
+                }
+                None => debug!("`{}` is in sync", wast_path.to_string_lossy()),
+            }
+        }
+        wasi_versions = stale;
+        if wasi_versions.is_empty() {
+            return Ok(Some((test, timings)));
+        }
+    }
+
+    // the versions write to distinct directories, so they're compiled in
+    // parallel, as many at once as `max_parallelism` allows. The tests are
+    // generated one at a time, each natively built alone first, so it bounds
+    // the compilations of the whole build.
+    let jobs = JobSlots::new(build_options.max_parallelism);
+    let results = thread::scope(|scope| {
+        let handles = wasi_versions
+            .iter()
+            .map(|&version| {
+                let test = &test;
+                let rs_mod_name = &rs_mod_name;
+                let jobs = &jobs;
+                let handle = scope.spawn(
+                    move || -> Result<Option<(PathBuf, PathBuf, WasmTimings)>, WasiTestError> {
+                        let out_dir = version_out_dir(base_dir, rel_dir, version, output_root);
+                        if !out_dir.exists() {
+                            fs::create_dir_all(&out_dir).map_err(WasiTestError::io(&out_dir))?;
+                        }
+                        let wasm_out_name = {
+                            let mut wasm_out_name = out_dir.join(rs_mod_name);
+                            wasm_out_name.set_extension("wast");
+                            wasm_out_name
+                        };
+                        info!("Writing test output to {}", wasm_out_name.to_string_lossy());
+                        let test_serialized = version_wast(version);
+                        if build_options.update {
+                            if let Some(diff) = wast_diff(&wasm_out_name, &test_serialized)? {
+                                info!("Updating `{}`:\n{}", wasm_out_name.to_string_lossy(), diff);
+                            }
+                        }
+                        util::write_atomically(&wasm_out_name, test_serialized)
+                            .map_err(WasiTestError::io(&wasm_out_name))?;
+                        write_fs_effects(&out_dir, test)?;
+
+                        let options = test.options.for_version(version);
+                        let _slot = jobs.take();
+                        info!("Compiling wasm version {:?}", version);
+                        let (wasm_path, timings) = compile_wasm_for_version(
+                            temp_dir,
+                            file,
+                            &out_dir,
+                            rs_mod_name,
+                            version,
+                            &options,
+                            build_options,
+                        )?;
+                        Ok(Some((out_dir, wasm_path, timings)))
+                    },
+                );
+                (version, handle)
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|(version, handle)| {
+                handle.join().unwrap_or_else(|_| {
+                    Err(WasiTestError::Execution {
+                        file: PathBuf::from(file),
+                        message: format!("the compilation to WASI version {:?} panicked", version),
+                    })
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+    // the first failure, once every version is done
+    let compiled = results
+        .into_iter()
+        .collect::<Result<Vec<_>, WasiTestError>>()?;
+
+    // one version at a time, as the runs share the filesystem
+    for (&version, compiled) in wasi_versions.iter().zip(compiled) {
+        let (out_dir, wasm_path, wasm_timings) = match compiled {
+            Some(compiled) => compiled,
+            None => continue,
+        };
+        timings.wasm.push(wasm_timings);
+        if let Some(wasmer) = &build_options.verify_with_wasmer {
+            info!("Verifying wasm version {:?} with wasmer", version);
+            let test = test.for_version(version);
+            let verified =
+                verify_with_wasmer(wasmer, file, &wasm_path, version, &test, build_options);
+            match (&test.options.xfail, verified) {
+                (None, verified) => verified?,
+                (Some(_), Err(WasiTestError::WasmerMismatch { .. })) => {
+                    info!("`{}` fails with wasmer, as expected", file)
+                }
+                (Some(_), Ok(())) => warn!(
+                    "`{}` is expected to fail, but it passes with wasmer: remove its `xfail`",
+                    file
+                ),
+                (Some(_), Err(e)) => return Err(e),
+            }
+        }
+        // only once it's verified, so that a failing test is generated again
+        let hash_path = hash_file_path(&out_dir, &rs_mod_name);
+        fs::write(
+            &hash_path,
+            build_hash(
+                &hash_input,
+                &compiler_name(language, version, build_options),
+            ),
+        )
+        .map_err(WasiTestError::io(&hash_path))?;
+    }
+
+    Ok(Some((test, timings)))
+}
+
+/// Runs the native program of the test in `file` with its `options`, and
+/// checks it behaves like they expect. Returns the test of its output, with
+/// how long building and running the native programs took, or `None` if it
+/// can't run here.
+fn native_test(
+    temp_dir: &Path,
+    file: &str,
+    rs_mod_name: &str,
+    language: Language,
+    options: WasiOptions,
+    build_options: &BuildOptions,
+) -> Result<Option<(WasiTest, Duration, Duration)>, WasiTestError> {
+    let execute_dir = build_options.execute_dir.as_path();
+    // removed when dropped, after the native program has run
+    let mut sparse_files = vec![];
+    for sparse_file in &options.sparse_file {
+        let created = CreatedSparseFile::create(sparse_file, execute_dir)
+            .map_err(WasiTestError::io(&execute_dir.join(&sparse_file.path)))?;
+        match created {
+            Some(created) => sparse_files.push(created),
+            None => {
+                info!(
+                    "Skipping `{}`: sparse files aren't supported on this platform or filesystem",
+                    file
+                );
+                return Ok(None);
+            }
+        }
+    }
+    let NativeOutput {
+        stdout,
+        stderr,
+        stdout_bytes,
+        stderr_bytes,
+        result,
+        trapped,
+        signal,
+        fs_effects,
+        tempdir_files,
+        compile_time,
+        run_time,
+    } = generate_native_output(
+        temp_dir,
+        file,
+        rs_mod_name,
+        &options.args,
+        &options,
+        build_options,
+        None,
+    )?
+    .expect("the host's program runs");
+    let mut native_compile_time = compile_time;
+    let mut native_run_time = run_time;
+
+    // the expected output is the host's, with that of the other targets where
+    // it differs
+    let mut native_platform = None;
+    let mut platform_outputs = vec![];
+    if !build_options.native_targets.is_empty() {
+        let host = host_triple(build_options.rustc.as_deref())?;
+        for target in &build_options.native_targets {
+            if language == Language::C {
+                info!(
+                    "Not building `{}` for `{}`, C tests are only built for the host",
+                    file, target
+                );
+                continue;
+            }
+            let output = generate_native_output(
+                temp_dir,
+                file,
+                rs_mod_name,
+                &options.args,
+                &options,
+                build_options,
+                Some(target),
+            )?;
+            let output = match output {
+                Some(output) => output,
+                None => {
+                    warn!(
+                        "`{}` programs can't run here, the expected output of `{}` is only `{}`'s",
+                        target, file, host
+                    );
+                    continue;
+                }
+            };
+            native_compile_time += output.compile_time;
+            native_run_time += output.run_time;
+            let platform_output = PlatformOutput {
+                target: target.clone(),
+                stdout: Some(output.stdout)
+                    .filter(|other| *other != stdout && options.stdout_contains.is_empty()),
+                // like the host's, the stderr of a trapping program isn't asserted
+                stderr: Some(output.stderr).filter(|other| {
+                    *other != stderr && options.trap.is_none() && options.stderr_contains.is_empty()
+                }),
+            };
+            if platform_output.stdout.is_some() || platform_output.stderr.is_some() {
+                info!("The output of `{}` differs on `{}`", file, target);
+                platform_outputs.push(platform_output);
+            }
+        }
+        native_platform = Some(host);
+    }
+    drop(sparse_files);
+
+    let execution_error = |message: String| WasiTestError::Execution {
+        file: PathBuf::from(file),
+        message,
+    };
+    if options.trap.is_some() && !trapped {
+        return Err(execution_error(format!(
+            "expected to trap, but it exited normally with {}",
+            result
+        )));
+    }
+    if let Some(signal) = signal {
+        info!(
+            "`{}` was killed by {}, a trap in Wasm",
+            file,
+            describe_signal(signal)
+        );
+    }
+    if let Some(exit) = &options.exit {
+        if !options.no_native_run && exit.code != result {
+            return Err(execution_error(format!(
+                "exited with {} instead of the {} of its `exit` directive",
+                result, exit
+            )));
+        }
+    }
+    for expected in &options.returns {
+        if let ExpectedReturn::ExitCode(code) = expected {
+            if *code != result {
+                return Err(execution_error(format!(
+                    "exited with {} instead of the {} it declares it returns",
+                    result, code
+                )));
+            }
+        }
+    }
+    for (name, output, texts) in [
+        ("stdout", &stdout, &options.stdout_contains),
+        ("stderr", &stderr, &options.stderr_contains),
+    ] {
+        if let Some(text) = texts.iter().find(|text| !output.contains(text.as_str())) {
+            return Err(execution_error(format!(
+                "its {} doesn't contain {:?}:\n{}",
+                name, text, output
+            )));
+        }
+    }
+    if options.assert_exit_matches_stdout {
+        match stdout.trim().parse::<i64>() {
+            Ok(printed) if printed != result => {
+                return Err(execution_error(format!(
+                    "exited with {}, a different value than it printed: {}",
+                    result, printed
+                )));
+            }
+            Ok(_) => (),
+            Err(_) => warn!(
+                "`{}` asserts its exit code matches its stdout, but its stdout isn't a number: {:?}",
+                file, stdout
+            ),
+        }
+    }
+    if options.assert_preopens.is_some() {
+        let listed = listed_preopens(&stdout);
+        let expected = options.expected_preopens();
+        if listed != expected {
+            return Err(execution_error(format!(
+                "the preopens it printed, {:?}, don't match its declared `dir`/`mapdir`/`tempdir`s, {:?}",
+                listed, expected
+            )));
+        }
+    }
+
+    let test = WasiTest {
+        wasm_prog_name: format!("{}.wasm", rs_mod_name),
+        stdout,
+        stderr,
+        stdout_bytes,
+        stderr_bytes,
+        // the only exit code known without the native run
+        result: options.exit.as_ref().map_or(result, |exit| exit.code),
+        options,
+        fs_effects,
+        native_platform,
+        platform_outputs,
+        tempdir_files,
+        meta: Some(WasiTestMeta {
+            native_compiler: compiler(language, build_options).version(None),
+            wasm_compiler: None,
+        }),
+    };
+    Ok(Some((test, native_compile_time, native_run_time)))
+}
+
+/// The versions of `wasi_versions` the test in `file` is generated for: those
+/// its `language` can be built for and that its `options` don't skip
+fn generated_versions(
+    file: &str,
+    language: Language,
+    options: &WasiOptions,
+    wasi_versions: &[WasiVersion],
+) -> Vec<WasiVersion> {
+    wasi_versions
+        .iter()
+        .copied()
+        .filter(|&version| {
+            let supported = language == Language::Rust || version.get_c_target_triple().is_some();
+            if !supported {
+                info!(
+                    "Skipping wasm version {:?} of `{}`: C tests aren't built for it",
+                    version, file
+                );
+                return false;
+            }
+            match &options.for_version(version).skip {
+                Some(reason) if reason.is_empty() => {
+                    info!("Skipping wasm version {:?} of `{}`", version, file);
+                    false
+                }
+                Some(reason) => {
+                    info!(
+                        "Skipping wasm version {:?} of `{}`: {}",
+                        version, file, reason
+                    );
+                    false
+                }
+                None => true,
+            }
+        })
+        .collect()
+}
+
+/// Whether `rustc +toolchain` failed with rustup's error for a toolchain that
+/// isn't installed
+fn is_toolchain_missing(stderr: &str) -> bool {
+    stderr.lines().any(|line| {
+        line.starts_with("error: toolchain '") && line.ends_with("' is not installed")
+            || line.starts_with("error: Missing manifest in toolchain '")
+    })
+}
+
+/// The name of the compiler of the tests in `language` for `version`: the
+/// toolchain, as in `compiler::rustc_command`, or the path of the compiler
+fn compiler_name(language: Language, version: WasiVersion, build_options: &BuildOptions) -> String {
+    compiler(language, build_options).name(version)
+}
+
+/// The compiler of the tests in `language`
+fn compiler(language: Language, build_options: &BuildOptions) -> Box<dyn Compiler + '_> {
+    match language {
+        Language::Rust => Box::new(Rustc {
+            rustc: build_options.rustc.as_deref(),
+        }),
+        Language::C => Box::new(Clang {
+            cc: &build_options.cc,
+            clang: &build_options.clang,
+        }),
+    }
+}
+
+/// Checks that the toolchain of `version` is installed. A compiler given
+/// instead of rustup's isn't checked, its failures are reported by the
+/// compilations.
+fn check_toolchain(version: WasiVersion, rustc: Option<&Path>) -> Result<(), WasiTestError> {
+    if rustc.is_some() {
+        return Ok(());
+    }
+    let toolchain = version.get_compiler_toolchain();
+    let out = rustc_command(Some(version), None)
+        .arg("--version")
+        .output()
+        .map_err(WasiTestError::io(Path::new("rustc")))?;
+    if !out.status.success() && is_toolchain_missing(&String::from_utf8_lossy(&out.stderr)) {
+        return Err(WasiTestError::ToolchainMissing {
+            version,
+            toolchain: toolchain.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// The hash identifying what the generated files of a test for a version are
+/// built from: the test's source, which includes its options, the version's
+/// compiler (see `compiler_name`) and the generator itself.
+fn build_hash(src_code: &str, compiler: &str) -> String {
+    let inputs = format!("{}\0{}\0{}", env!("CARGO_PKG_VERSION"), compiler, src_code);
+    format!("{:016x}", util::fnv1a_hash(inputs.as_bytes()))
+}
+
+/// The languages the tests can be written in, told apart by the extension of
+/// their file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Language {
+    Rust,
+    /// Built natively with `cc` and to Wasm with the `clang` of wasi-sdk
+    C,
+}
+
+impl Language {
+    /// The language of the test in `file`, if it's a test
+    fn of(file: &str) -> Option<Language> {
+        match Path::new(file).extension()?.to_str()? {
+            "rs" => Some(Language::Rust),
+            "c" => Some(Language::C),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Language::Rust => "rs",
+            Language::C => "c",
+        }
+    }
+}
+
+/// The name of the files generated from the test in `file`
+fn module_name(file: &str) -> String {
+    Path::new(&file.to_lowercase())
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The directory the files generated for `version` from the tests in `rel_dir`,
+/// relative to `base_dir`, are written to: in the version's directory in
+/// `output_root` if it's given, and next to `base_dir` otherwise
+fn version_out_dir(
+    base_dir: &Path,
+    rel_dir: &Path,
+    version: WasiVersion,
+    output_root: Option<&Path>,
+) -> PathBuf {
+    match output_root {
+        Some(output_root) => output_root.join(version.get_directory_name()).join(rel_dir),
+        None => base_dir
+            .join("..")
+            .join(version.get_directory_name())
+            .join(rel_dir),
+    }
+}
+
+/// The directory of `version`'s manifest and dashboard, with the generated
+/// files of the tests of `wasi/tests`: in `output_root` if it's given, and in
+/// `wasi` otherwise
+fn version_dir(version: WasiVersion, output_root: Option<&Path>) -> PathBuf {
+    output_root
+        .unwrap_or_else(|| Path::new(WASI_DIR))
+        .join(version.get_directory_name())
+}
+
+/// Splits the path of a test file into the directory its version directories
+/// are next to and the subdirectory it's in, which the generated files mirror.
+/// Files outside of `wasi/tests` are taken as top-level tests of their directory.
+fn split_test_path(file: &Path) -> (PathBuf, PathBuf) {
+    let parent = file.parent().unwrap();
+    let tests_dir = Path::new(WASI_DIR).join("tests");
+    match parent.strip_prefix(&tests_dir) {
+        Ok(rel_dir) => (tests_dir.clone(), rel_dir.to_path_buf()),
+        Err(_) => (parent.to_path_buf(), PathBuf::new()),
+    }
+}
+
+/// The file storing the `build_hash` of the files generated in `out_dir`
+fn hash_file_path(out_dir: &Path, rs_mod_name: &str) -> PathBuf {
+    out_dir.join(format!("{}.wast.hash", rs_mod_name))
+}
+
+/// Whether the `.wast` and `.wasm` of a test for `version` were built from
+/// the same inputs and don't need to be generated again
+fn is_up_to_date(out_dir: PathBuf, rs_mod_name: &str, src_code: &str, compiler: &str) -> bool {
+    let wasm_path = out_dir.join(format!("{}.wasm", rs_mod_name));
+    match fs::read_to_string(hash_file_path(&out_dir, rs_mod_name)) {
+        Ok(hash) => wasm_path.exists() && hash.trim() == build_hash(src_code, compiler),
+        Err(_) => false,
+    }
+}
+
+/// The ```` ```wasi ```` blocks in the doc comments of the test `file`, which
+/// only Rust tests have
+fn wasi_doctests(file: &str, src_code: &str) -> Vec<String> {
+    match Language::of(file) {
+        Some(Language::Rust) => extract_wasi_doctests(src_code),
+        _ => vec![],
+    }
+}
+
+/// Compiles every ```` ```wasi ```` block in the doc comments of `file` as its
+/// own test, named `<module>_doctest_<index>`.
+///
+/// Returns the tests created, with how long they took.
+fn compile_doctests(
+    temp_dir: &Path,
+    file: &str,
+    base_dir: &Path,
+    rel_dir: &Path,
+    wasi_versions: &[WasiVersion],
+    build_options: &BuildOptions,
+    defaults: &TestDefaults,
+) -> Result<Vec<(WasiTest, TestTimings)>, WasiTestError> {
+    let src_code: String = fs::read_to_string(file).map_err(WasiTestError::io(Path::new(file)))?;
+    let rs_mod_name = module_name(file);
+
+    let mut tests = vec![];
+    for (i, doctest) in extract_wasi_doctests(&src_code).iter().enumerate() {
+        let doctest_file = temp_dir.join(format!("{}_doctest_{}.rs", rs_mod_name, i));
+        debug!(
+            "Extracting doctest {} of `{}` to `{}`",
+            i,
+            file,
+            doctest_file.to_string_lossy()
+        );
+        fs::write(&doctest_file, doctest).map_err(WasiTestError::io(&doctest_file))?;
+        tests.extend(compile(
+            temp_dir,
+            doctest_file.to_str().unwrap(),
+            base_dir,
+            rel_dir,
+            wasi_versions,
+            build_options,
+            defaults,
+        )?);
+    }
+    Ok(tests)
+}
+
+/// The options controlling which tests `build` generates
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    /// Also generate a test from every ```` ```wasi ```` block in the doc
+    /// comments of the test files
+    pub doctests: bool,
+    /// Write a `dashboard.json` to each version's directory, summarizing what
+    /// the tests generated by this run validate
+    pub dashboard: bool,
+    /// Generate every test, even those whose source and toolchain haven't
+    /// changed since they were last generated
+    pub force: bool,
+    /// How long a native test program may run before it's killed, which fails
+    /// its generation
+    pub native_timeout: Duration,
+    /// Run every native program twice and fail the tests whose output, exit
+    /// code or effects differ between the runs, as their expected output
+    /// can't be relied on
+    pub check_determinism: bool,
+    /// How many times a compilation is run while it fails for what looks like
+    /// a transient reason, like a busy machine, rather than the source. `1`,
+    /// the default, doesn't retry.
+    pub compile_attempts: usize,
+    /// Leave the Wasm modules as `rustc` outputs them, without running
+    /// `wasm-strip` and `wasm-opt` on them
+    pub skip_post_processing: bool,
+    /// The most Wasm compilations, with the post-processing of their modules,
+    /// run at once, or `None` or `0` for no bound. Each `rustc` is already
+    /// parallel, so a few at once are usually best. It defaults to the number
+    /// of CPUs.
+    pub max_parallelism: Option<usize>,
+    /// The versions the post-processing tools are expected to have, by tool
+    /// name, warning when the installed one's `--version` doesn't contain it
+    pub pinned_tools: Vec<(String, String)>,
+    /// Also write the text format of the Wasm of every test to a `.wat` next
+    /// to it, with `wasm2wat`, to read what it imports. Up-to-date tests
+    /// aren't regenerated for it.
+    pub emit_wat: bool,
+    /// Check that the Wasm of every test is valid once it's post-processed,
+    /// failing its generation otherwise
+    pub validate_wasm: bool,
+    /// Run the Wasm of every test with the `wasmer` binary at this path and
+    /// check that it behaves like the native program, failing its generation
+    /// otherwise
+    pub verify_with_wasmer: Option<PathBuf>,
+    /// Take the current size of the Wasm modules as the baseline they're
+    /// compared to, instead of failing when they grew
+    pub update_size_baseline: bool,
+    /// How much the Wasm of a test may grow over its baseline, in percent,
+    /// before the build fails
+    pub size_tolerance: f64,
+    /// Turn the `\r\n`s of the native output into `\n`s, unless a test's
+    /// `normalize_line_endings` directive says otherwise. On by default on
+    /// Windows, where native programs may write `\r\n` where Wasm ones write `\n`.
+    pub normalize_line_endings: bool,
+    /// Go on with the other tests when one fails to generate, and return all
+    /// their errors at the end
+    pub keep_going: bool,
+    /// Fail when the toolchain of one of the WASI versions isn't installed,
+    /// instead of generating the tests for the other versions only
+    pub strict_toolchains: bool,
+    /// Copy the modified source of a test that fails to compile to Wasm out of
+    /// the temporary directory, to see exactly what was compiled
+    pub keep_temp: bool,
+    /// The directory the native programs run in, which the paths of the test
+    /// options, like their preopened directories, are relative to
+    pub execute_dir: PathBuf,
+    /// Only generate the tests whose file matches, skipping the others before
+    /// anything is compiled
+    pub filter: Option<TestFilter>,
+    /// The files of `wasi/tests` that aren't tests, like helper modules, by
+    /// name like the globs of `filter`. Those whose name starts with `_` never
+    /// are.
+    pub exclude: Vec<glob::Pattern>,
+    /// The directory the version directories of the generated files are
+    /// written to, instead of next to the tests, as `wasi/snapshot1` for
+    /// `wasi/tests`. The subdirectories of the tests are kept.
+    pub output_root: Option<PathBuf>,
+    /// Fail when there's no test file at all, instead of only warning, as it
+    /// means the tests aren't where they're looked for
+    pub require_tests: bool,
+    /// Remove the generated files of the tests whose source doesn't exist
+    /// anymore, instead of only warning about them
+    pub prune: bool,
+    /// Only run the tests natively and print their `.wast` to stdout, without
+    /// compiling them to Wasm or writing any file, to check their options
+    /// quickly. The tests are run even if they're up to date, and the files
+    /// `prune` would remove are only listed.
+    pub dry_run: bool,
+    /// Only run the tests natively and compare their `.wast` with the one
+    /// already generated, failing if one differs, without compiling them to
+    /// Wasm or writing any file. Every test is checked, even if one fails.
+    pub check: bool,
+    /// Other targets than the host's to build the native programs for, by
+    /// triple. Those that can run here are run, and the expected output on
+    /// those where it differs from the host's is added to the `.wast`, with
+    /// the host's triple.
+    pub native_targets: Vec<String>,
+    /// Run the tests that are up to date natively too, rewriting their
+    /// `.wast` if their output changed, without building their Wasm again.
+    /// The diff of every `.wast` that changes is logged.
+    pub update: bool,
+    /// The Rust compiler the tests are built with, for every WASI version and
+    /// without a `+toolchain`, instead of rustup's `rustc` with the toolchain
+    /// of each version. It defaults to the `RUSTC` environment variable, like
+    /// for Cargo.
+    pub rustc: Option<PathBuf>,
+    /// The C compiler the C tests are built natively with
+    pub cc: PathBuf,
+    /// The `clang` of wasi-sdk the C tests are built to Wasm with, which has
+    /// its sysroot
+    pub clang: PathBuf,
+}
+
+impl BuildOptions {
+    /// Whether the tests are only run natively, without writing or removing
+    /// any file
+    fn writes_nothing(&self) -> bool {
+        self.dry_run || self.check
+    }
+
+    /// The options that change the files generated for a test, so that
+    /// changing them regenerates it
+    fn output_settings(&self) -> String {
+        format!(
+            "skip_post_processing={} normalize_line_endings={} execute_dir={}",
+            self.skip_post_processing,
+            self.normalize_line_endings,
+            self.execute_dir.display()
+        )
+    }
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            doctests: false,
+            dashboard: false,
+            force: false,
+            native_timeout: DEFAULT_NATIVE_TIMEOUT,
+            check_determinism: false,
+            compile_attempts: 1,
+            skip_post_processing: false,
+            max_parallelism: jobs::default_parallelism(),
+            pinned_tools: vec![],
+            emit_wat: false,
+            validate_wasm: true,
+            verify_with_wasmer: None,
+            update_size_baseline: false,
+            size_tolerance: DEFAULT_SIZE_TOLERANCE,
+            normalize_line_endings: cfg!(windows),
+            keep_going: false,
+            strict_toolchains: false,
+            keep_temp: false,
+            execute_dir: PathBuf::from(DEFAULT_EXECUTE_DIR),
+            filter: None,
+            exclude: vec![],
+            output_root: None,
+            require_tests: false,
+            prune: false,
+            dry_run: false,
+            check: false,
+            native_targets: vec![],
+            update: false,
+            rustc: std::env::var_os("RUSTC").map(PathBuf::from),
+            cc: PathBuf::from("cc"),
+            clang: PathBuf::from("clang"),
+        }
+    }
+}
+
+/// Which test files `build` generates, by the name of their module, prefixed
+/// with their subdirectory of `wasi/tests` if any (like `sub/fd_read`)
+#[derive(Debug, Clone)]
+pub enum TestFilter {
+    /// The names containing it
+    Substring(String),
+    /// The names matching it as a whole
+    Glob(glob::Pattern),
+}
+
+impl TestFilter {
+    /// A glob if `pattern` is one with wildcards (`*`, `?` or `[...]`), and a
+    /// substring otherwise
+    pub fn new(pattern: &str) -> Self {
+        if pattern.contains(&['*', '?', '['][..]) {
+            if let Ok(glob) = glob::Pattern::new(pattern) {
+                return TestFilter::Glob(glob);
+            }
+        }
+        TestFilter::Substring(pattern.to_string())
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            TestFilter::Substring(substring) => name.contains(substring.as_str()),
+            TestFilter::Glob(glob) => glob.matches(name),
+        }
+    }
+}
+
+impl std::str::FromStr for TestFilter {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(TestFilter::new(s))
+    }
+}
+
+pub const DEFAULT_NATIVE_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_SIZE_TOLERANCE: f64 = 10.0;
+
+/// Checks that the `.wast` of every test is what it outputs now, without
+/// writing any file, with the default `BuildOptions` otherwise
+pub fn check(wasi_versions: &[WasiVersion]) -> Result<(), WasiTestError> {
+    build(
+        wasi_versions,
+        &BuildOptions {
+            check: true,
+            ..BuildOptions::default()
+        },
+    )
+}
+
+/// Generates the tests whose name matches `filter` (see `TestFilter::new`),
+/// with the default `BuildOptions`
+pub fn build_filtered(wasi_versions: &[WasiVersion], filter: &str) -> Result<(), WasiTestError> {
+    build(
+        wasi_versions,
+        &BuildOptions {
+            filter: Some(TestFilter::new(filter)),
+            ..BuildOptions::default()
+        },
+    )
+}
+
+/// The endings of the names of the files `build` generates for each test
+const GENERATED_TEST_FILES: &[&str] = &[".wast", ".wasm", ".wat", ".wast.hash", ".fs_effects.json"];
+/// The files `build` generates for each version, in its directory
+const GENERATED_VERSION_FILES: &[&str] = &["manifest.json", "dashboard.json"];
+
+/// The name of the test a file named `file_name` is generated for, if it's
+/// one of the files `build` generates for each test
+fn generated_test_name(file_name: &str) -> Option<&str> {
+    GENERATED_TEST_FILES
+        .iter()
+        .filter_map(|ending| file_name.strip_suffix(ending))
+        .find(|name| !name.is_empty())
+}
+
+/// Finds the files generated in `dir`, a version directory or one of its
+/// subdirectories, for tests whose path relative to the version directory
+/// isn't in `expected`, as their source was removed or renamed. `rel_dir` is
+/// the path of `dir` relative to the version directory.
+fn find_stale_files(
+    dir: &Path,
+    rel_dir: &Path,
+    expected: &HashSet<PathBuf>,
+    stale: &mut Vec<PathBuf>,
+) -> Result<(), WasiTestError> {
+    let io_error = WasiTestError::io(dir);
+    for entry in fs::read_dir(dir).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type().map_err(io_error)?.is_dir() {
+            find_stale_files(&entry.path(), &rel_dir.join(&name), expected, stale)?;
+        } else if let Some(test_name) = generated_test_name(&name) {
+            if !expected.contains(&rel_dir.join(test_name)) {
+                stale.push(entry.path());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The paths of the tests generated from the `test_files` of `build`,
+/// relative to the version directories, including their doc comment examples
+fn expected_test_names(
+    test_files: &[(String, PathBuf, PathBuf)],
+) -> Result<HashSet<PathBuf>, WasiTestError> {
+    let mut expected = HashSet::new();
+    for (file, _, rel_dir) in test_files {
+        let src_code = fs::read_to_string(file).map_err(WasiTestError::io(Path::new(file)))?;
+        let rs_mod_name = module_name(file);
+        for i in 0..wasi_doctests(file, &src_code).len() {
+            expected.insert(rel_dir.join(format!("{}_doctest_{}", rs_mod_name, i)));
+        }
+        expected.insert(rel_dir.join(rs_mod_name));
+    }
+    Ok(expected)
+}
+
+/// Removes the files generated for `wasi_versions` in their directories: the
+/// `.wast`, `.wasm`, hash and filesystem effects files of the tests, including
+/// those of tests that were renamed or removed since, and the manifests and
+/// dashboards. The files are told apart by their names, so nothing else in
+/// the version directories is touched, and the subdirectories left empty are
+/// removed. Returns the paths of the removed files.
+pub fn clean(
+    wasi_versions: &[WasiVersion],
+    output_root: Option<&Path>,
+) -> Result<Vec<PathBuf>, WasiTestError> {
+    let mut removed = vec![];
+    for &version in wasi_versions {
+        let version_dir = version_dir(version, output_root);
+        if version_dir.is_dir() {
+            info!("Cleaning {}", version_dir.to_string_lossy());
+            clean_dir(&version_dir, GENERATED_VERSION_FILES, &mut removed)?;
+        }
+    }
+    info!("Removed {} generated files", removed.len());
+    Ok(removed)
+}
+
+/// Removes the generated files of the tests in `dir` and its subdirectories,
+/// along with those named `generated_files`, adding them to `removed`
+fn clean_dir(
+    dir: &Path,
+    generated_files: &[&str],
+    removed: &mut Vec<PathBuf>,
+) -> Result<(), WasiTestError> {
+    let io_error = WasiTestError::io(dir);
+    for entry in fs::read_dir(dir).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        // not following symlinks, whose targets weren't generated here
+        if entry.file_type().map_err(io_error)?.is_dir() {
+            // the directories of the tests in subdirectories of `wasi/tests`
+            clean_dir(&path, &[], removed)?;
+            let is_empty = fs::read_dir(&path)
+                .map_err(WasiTestError::io(&path))?
+                .next()
+                .is_none();
+            if is_empty {
+                fs::remove_dir(&path).map_err(WasiTestError::io(&path))?;
+            }
+        } else if generated_files.contains(&name.as_str()) || generated_test_name(&name).is_some() {
+            fs::remove_file(&path).map_err(WasiTestError::io(&path))?;
+            debug!("Removed `{}`", path.to_string_lossy());
+            removed.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Generates the test in `file`, plus those in its doc comments if
+/// `build_options.doctests` is set, exactly as `build` does for each test file.
+///
+/// `file` must be a `.rs` or `.c` file. The generated files of a test in a
+/// subdirectory of `wasi/tests` are written to the same subdirectory of the
+/// version directories, and those of any other file to the version
+/// directories next to its directory. `temp_dir` holds the intermediate files,
+/// in a directory of their own for each call, so it can be shared by several
+/// calls, even concurrent ones for tests with the same name. Returns the
+/// tests created, which doesn't include those that were skipped or up to date.
+///
+/// Like with `build`, the options of `wasi/tests/_defaults.toml` are merged
+/// into those of the tests.
+pub fn build_single(
+    temp_dir: &Path,
+    file: &str,
+    wasi_versions: &[WasiVersion],
+    build_options: &BuildOptions,
+) -> Result<Vec<WasiTest>, WasiTestError> {
+    Ok(build_file(temp_dir, file, wasi_versions, build_options)?
+        .into_iter()
+        .map(|(test, _)| test)
+        .collect())
+}
+
+/// A test generated by `generate_test`, without writing anything next to the
+/// tests
+#[derive(Debug, Clone)]
+pub struct GeneratedTest {
+    /// What the `.wast`s would hold, with the directives of every version:
+    /// `WasiTest::for_version` gives the test of one
+    pub test: WasiTest,
+    /// The Wasm module built for each version, in the temp dir
+    pub wasm: Vec<(WasiVersion, PathBuf)>,
+}
+
+/// Generates the test in `file` for `wasi_versions` like `build_single`, but
+/// returns it instead of writing its `.wast`, Wasm and other files: for tools
+/// running it themselves. Its Wasm modules are left in `temp_dir`, which the
+/// caller removes, so they must not share it with another call for a test
+/// with the same name. Returns `None` if the test is skipped.
+///
+/// The test is generated even if its `.wast`s are up to date, and its doc
+/// comment examples aren't. The options of `wasi/tests/_defaults.toml` are
+/// merged into those of the test, and `verify_with_wasmer` is ignored.
+pub fn generate_test(
+    temp_dir: &Path,
+    file: &str,
+    wasi_versions: &[WasiVersion],
+    build_options: &BuildOptions,
+) -> Result<Option<GeneratedTest>, WasiTestError> {
+    let defaults = read_test_defaults()?;
+    let (base_dir, rel_dir) = split_test_path(Path::new(file));
+    let src_code: String = fs::read_to_string(file).map_err(WasiTestError::io(Path::new(file)))?;
+    let sidecar = read_sidecar(file)?;
+    let options = test_options(
+        file,
+        &src_code,
+        sidecar.as_deref(),
+        &defaults,
+        &base_dir.join(&rel_dir),
+        &build_options.execute_dir,
+    )?;
+    if options.skip.is_some() {
+        info!("Skipping `{}`", file);
+        return Ok(None);
+    }
+    let language = Language::of(file).ok_or_else(|| WasiTestError::Parse {
+        file: PathBuf::from(file),
+        message: "only `.rs` and `.c` files are tests".to_string(),
+    })?;
+    let rs_mod_name = module_name(file);
+    let wasi_versions = generated_versions(file, language, &options, wasi_versions);
+    let test = match native_test(
+        temp_dir,
+        file,
+        &rs_mod_name,
+        language,
+        options,
+        build_options,
+    )? {
+        Some((test, _, _)) => test,
+        None => return Ok(None),
+    };
+
+    let mut wasm = vec![];
+    for version in wasi_versions {
+        let options = test.options.for_version(version);
+        let (wasm_path, _) = build_wasm(
+            temp_dir,
+            file,
+            &rs_mod_name,
+            version,
+            &options,
+            build_options,
+        )?;
+        wasm.push((version, wasm_path));
+    }
+    Ok(Some(GeneratedTest { test, wasm }))
+}
+
+/// `build_single`, also returning how long each test took
+fn build_file(
+    temp_dir: &Path,
+    file: &str,
+    wasi_versions: &[WasiVersion],
+    build_options: &BuildOptions,
+) -> Result<Vec<(WasiTest, TestTimings)>, WasiTestError> {
+    let defaults = read_test_defaults()?;
+    let (base_dir, rel_dir) = split_test_path(Path::new(file));
+    // removed when dropped, after the test is built
+    let test_temp_dir = tempfile::Builder::new()
+        .prefix(&format!("{}-", module_name(file)))
+        .tempdir_in(temp_dir)
+        .map_err(WasiTestError::io(temp_dir))?;
+    let temp_dir = test_temp_dir.path();
+    let mut tests = compile(
+        temp_dir,
+        file,
+        &base_dir,
+        &rel_dir,
+        wasi_versions,
+        build_options,
+        &defaults,
+    )?
+    .into_iter()
+    .collect::<Vec<(WasiTest, TestTimings)>>();
+    if build_options.doctests && Language::of(file) == Some(Language::Rust) {
+        tests.extend(compile_doctests(
+            temp_dir,
+            file,
+            &base_dir,
+            &rel_dir,
+            wasi_versions,
+            build_options,
+            &defaults,
+        )?);
+    }
+    Ok(tests)
+}
+
+/// The manifest entries of the tests generated from `file`, its doc comment
+/// examples included, whose `.wast` for `version` exists. Tests that were up
+/// to date are listed like those generated by this run, and skipped tests
+/// without their Wasm.
+fn manifest_entries(
+    file: &str,
+    base_dir: &Path,
+    rel_dir: &Path,
+    version: WasiVersion,
+    defaults: &TestDefaults,
+    execute_dir: &Path,
+    output_root: Option<&Path>,
+) -> Result<Vec<ManifestEntry>, WasiTestError> {
+    let src_code: String = fs::read_to_string(file).map_err(WasiTestError::io(Path::new(file)))?;
+    let source = Path::new(file)
+        .strip_prefix(WASI_DIR)
+        .unwrap_or_else(|_| Path::new(file))
+        .to_string_lossy()
+        .to_string();
+    let rs_mod_name = module_name(file);
+    let doctests = wasi_doctests(file, &src_code)
+        .into_iter()
+        .enumerate()
+        .map(|(i, doctest)| (format!("{}_doctest_{}", rs_mod_name, i), doctest));
+    let out_dir = version_out_dir(base_dir, rel_dir, version, output_root);
+    let sidecar = read_sidecar(file)?;
+
+    let mut entries = vec![];
+    for (name, code) in std::iter::once((rs_mod_name.clone(), src_code.clone())).chain(doctests) {
+        // the doc comment examples only have their own header
+        let sidecar = sidecar.as_deref().filter(|_| name == rs_mod_name);
+        let options = test_options(
+            file,
+            &code,
+            sidecar,
+            defaults,
+            &base_dir.join(rel_dir),
+            execute_dir,
+        )?
+        .for_version(version);
+        let wasm = if options.skip.is_some() {
+            None
+        } else if out_dir.join(format!("{}.wast", name)).exists() {
+            Some(
+                rel_dir
+                    .join(format!("{}.wasm", name))
+                    .to_string_lossy()
+                    .to_string(),
+            )
+        } else {
+            continue;
+        };
+        let wasm_size = wasm
+            .as_ref()
+            .and_then(|_| fs::metadata(out_dir.join(format!("{}.wasm", name))).ok())
+            .map(|metadata| metadata.len());
+        entries.push(ManifestEntry {
+            name: rel_dir.join(&name).to_string_lossy().to_string(),
+            wasm,
+            source: source.clone(),
+            skipped: options.skip.clone(),
+            options,
+            wasm_size,
+            wasm_size_baseline: None,
+        });
+    }
+    Ok(entries)
+}
+
+/// The test files, in each language
+const WASI_TEST_SRC_PATTERNS: &[&str] = &[
+    concat!(env!("CARGO_MANIFEST_DIR"), "/wasi/tests/**/*.rs"),
+    concat!(env!("CARGO_MANIFEST_DIR"), "/wasi/tests/**/*.c"),
+];
+pub fn build(
+    wasi_versions: &[WasiVersion],
+    build_options: &BuildOptions,
+) -> Result<(), WasiTestError> {
+    let mut installed_versions = vec![];
+    let mut first_missing = None;
+    for &version in wasi_versions {
+        match check_toolchain(version, build_options.rustc.as_deref()) {
+            Ok(()) => installed_versions.push(version),
+            Err(e @ WasiTestError::ToolchainMissing { .. }) if !build_options.strict_toolchains => {
+                warn!("Skipping WASI version {:?}: {}", version, e);
+                first_missing.get_or_insert(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if installed_versions.is_empty() {
+        if let Some(e) = first_missing {
+            return Err(e);
+        }
+    }
+    let wasi_versions = &installed_versions[..];
+
+    // what the modules generated by this run are post-processed with
+    let post_processing_tools =
+        if build_options.skip_post_processing || build_options.writes_nothing() {
+            BTreeMap::new()
+        } else {
+            let tools = post_processing_tool_versions();
+            check_pinned_tools(&tools, &build_options.pinned_tools);
+            tools
+        };
+
+    let output_root = build_options.output_root.as_deref();
+    let temp_dir = tempfile::TempDir::new().map_err(WasiTestError::io(&std::env::temp_dir()))?;
+    // each test with the directory its version directories are next to and
+    // its subdirectory in them
+    let mut generated_tests = vec![];
+    // how long each generated test took, by name
+    let mut timings = vec![];
+    // every test file, with the same directories
+    let mut test_files = vec![];
+    // those not filtered out
+    let mut matched_files = 0;
+    let mut errors = vec![];
+    for entry in WASI_TEST_SRC_PATTERNS
+        .iter()
+        .flat_map(|pattern| glob(pattern).unwrap())
+    {
+        match entry {
+            Ok(path) => {
+                let test = path.to_str().unwrap();
+                let (base_dir, rel_dir) = split_test_path(&path);
+                let name = rel_dir.join(module_name(test));
+                if module_name(test).starts_with('_')
+                    || build_options
+                        .exclude
+                        .iter()
+                        .any(|pattern| pattern.matches(&name.to_string_lossy()))
+                {
+                    debug!("Skipping `{}`, it isn't a test", test);
+                    continue;
+                }
+                // still listed in the manifests
+                test_files.push((test.to_string(), base_dir.clone(), rel_dir.clone()));
+                if let Some(filter) = &build_options.filter {
+                    if !filter.matches(&name.to_string_lossy()) {
+                        info!("Skipping `{}`, it doesn't match the filter", test);
+                        continue;
+                    }
+                }
+                matched_files += 1;
+                match build_file(temp_dir.path(), test, wasi_versions, build_options) {
+                    Ok(tests) => {
+                        for (t, test_timings) in tests {
+                            let name = rel_dir.join(&t.wasm_prog_name).with_extension("");
+                            timings.push((name.to_string_lossy().to_string(), test_timings));
+                            generated_tests.push((base_dir.clone(), rel_dir.clone(), t));
+                        }
+                    }
+                    // the diff now, as the error at the end only lists the tests
+                    Err(e @ WasiTestError::OutOfSync { .. }) => {
+                        error!("{}", e);
+                        errors.push(e);
+                    }
+                    Err(e) if build_options.keep_going || build_options.check => {
+                        let action = if build_options.check {
+                            "check"
+                        } else {
+                            "generate"
+                        };
+                        error!("Failed to {} `{}`, keeping going", action, test);
+                        errors.push(e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            // a test file that can't be read fails like one that doesn't build
+            Err(e) => {
+                let e = WasiTestError::Io {
+                    file: e.path().to_path_buf(),
+                    source: e.into_error(),
+                };
+                if !(build_options.keep_going || build_options.check) {
+                    return Err(e);
+                }
+                error!("{}, keeping going", e);
+                errors.push(e);
+            }
+        }
+    }
+    if test_files.is_empty() {
+        // most likely the tests aren't where they're looked for
+        if build_options.require_tests {
+            return Err(WasiTestError::NoTests {
+                pattern: WASI_TEST_SRC_PATTERNS.join("` or `"),
+            });
+        }
+        warn!(
+            "No test file matches `{}`",
+            WASI_TEST_SRC_PATTERNS.join("` or `")
+        );
+    } else if build_options.filter.is_some() && matched_files == 0 {
+        warn!("No test file matches the filter");
+    }
+    if !errors.is_empty() {
+        if !errors
+            .iter()
+            .all(|e| matches!(e, WasiTestError::OutOfSync { .. }))
+        {
+            return Err(WasiTestError::Tests(errors));
+        }
+        let files = errors
+            .into_iter()
+            .filter_map(|e| match e {
+                WasiTestError::OutOfSync { file, .. } => Some(file),
+                _ => None,
+            })
+            .collect();
+        return Err(WasiTestError::OutOfDate { files });
+    }
+    info!("All modules generated.");
+    if !timings.is_empty() {
+        info!("Time spent on each test:\n{}", timings_table(&mut timings));
+    }
+
+    if build_options.dashboard && !build_options.writes_nothing() {
+        for &version in wasi_versions {
+            if version.is_component() {
+                // the WASI functions are only read from the imports of core modules
+                warn!(
+                    "No dashboard for WASI version {:?}, its programs are components",
+                    version
+                );
+                continue;
+            }
+            let tests = generated_tests
+                .iter()
+                .map(|(base_dir, rel_dir, test)| {
+                    // named by their path, to tell those in subdirectories apart
+                    let name = rel_dir
+                        .join(&test.wasm_prog_name)
+                        .with_extension("")
+                        .to_string_lossy()
+                        .to_string();
+                    let wasm_path = version_out_dir(base_dir, rel_dir, version, output_root)
+                        .join(&test.wasm_prog_name);
+                    (name, wasm_path, test)
+                })
+                .collect::<Vec<_>>();
+            let dashboard_path = version_dir(version, output_root).join("dashboard.json");
+            write_dashboard(&dashboard_path, version, &tests)
+                .map_err(WasiTestError::io(&dashboard_path))?;
+        }
+    }
+
+    // without test files, every generated file would look stale
+    if !test_files.is_empty() {
+        let expected = expected_test_names(&test_files)?;
+        for &version in wasi_versions {
+            let version_dir = version_dir(version, output_root);
+            if !version_dir.is_dir() {
+                continue;
+            }
+            let mut stale = vec![];
+            find_stale_files(&version_dir, Path::new(""), &expected, &mut stale)?;
+            stale.sort();
+            for path in stale {
+                if !build_options.prune {
+                    warn!(
+                        "`{}` is stale, its test doesn't exist anymore (remove it with --prune)",
+                        path.to_string_lossy()
+                    );
+                } else if build_options.writes_nothing() {
+                    info!("Would remove stale `{}`", path.to_string_lossy());
+                } else {
+                    info!("Removing stale `{}`", path.to_string_lossy());
+                    fs::remove_file(&path).map_err(WasiTestError::io(&path))?;
+                }
+            }
+        }
+    }
+
+    if build_options.dry_run {
+        info!("Dry run: no file was written or removed");
+        return Ok(());
+    }
+    if build_options.check {
+        info!("Every `.wast` is in sync");
+        return Ok(());
+    }
+
+    // reported once every manifest is written
+    let mut size_regressions = vec![];
+    let defaults = read_test_defaults()?;
+    for &version in wasi_versions {
+        let mut entries = vec![];
+        for (file, base_dir, rel_dir) in &test_files {
+            entries.extend(manifest_entries(
+                file,
+                base_dir,
+                rel_dir,
+                version,
+                &defaults,
+                &build_options.execute_dir,
+                output_root,
+            )?);
+        }
+        let manifest_path = version_dir(version, output_root).join("manifest.json");
+        let baselines =
+            read_size_baselines(&manifest_path).map_err(WasiTestError::io(&manifest_path))?;
+        let changes =
+            apply_size_baselines(&mut entries, &baselines, build_options.update_size_baseline);
+        let previous_tools = read_post_processing_tools(&manifest_path)
+            .map_err(WasiTestError::io(&manifest_path))?;
+        // those of the run that last generated tests, as the modules of the
+        // others are left as they are
+        let tools = if version.is_component() {
+            BTreeMap::new()
+        } else if generated_tests.is_empty() {
+            previous_tools
+        } else {
+            for (tool, version) in &post_processing_tools {
+                match previous_tools.get(tool) {
+                    Some(previous) if previous != version => warn!(
+                        "`{}` changed from `{}` to `{}`, which may change every module it post-processes",
+                        tool, previous, version
+                    ),
+                    _ => (),
+                }
+            }
+            post_processing_tools.clone()
+        };
+        write_manifest(&manifest_path, version, tools, entries)
+            .map_err(WasiTestError::io(&manifest_path))?;
+
+        if !changes.is_empty() {
+            info!("Wasm size changes for WASI version {:?}:", version);
+            for change in &changes {
+                info!("  {}", change);
+            }
+        }
+        let regressions = changes
+            .into_iter()
+            .filter(|change| change.growth() > build_options.size_tolerance)
+            .collect::<Vec<_>>();
+        if !regressions.is_empty() {
+            size_regressions.push(WasiTestError::WasmSizeRegression {
+                version,
+                tolerance: build_options.size_tolerance,
+                regressions,
+            });
+        }
+    }
+    match size_regressions.len() {
+        0 => Ok(()),
+        1 => Err(size_regressions.pop().unwrap()),
+        _ => Err(WasiTestError::Tests(size_regressions)),
+    }
+}
+
+/// This is the structure of the `.wast` file
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WasiTest {
+    /// The name of the wasm module to run
+    pub wasm_prog_name: String,
+    /// The program expected output on stdout
+    pub stdout: String,
+    /// The program expected output on stderr
+    pub stderr: String,
+    /// The program expected output on stdout, when it isn't UTF-8. `stdout`
+    /// then holds it lossily.
+    pub stdout_bytes: Option<Vec<u8>>,
+    /// The program expected output on stderr, when it isn't UTF-8. `stderr`
+    /// then holds it lossily.
+    pub stderr_bytes: Option<Vec<u8>>,
+    /// The program expected result
+    pub result: i64,
+    /// The program options
+    pub options: WasiOptions,
+    /// The changes the program is expected to make to the recorded trees
+    pub fs_effects: Vec<FsEffect>,
+    /// The target triple of the native program the expected output is from,
+    /// recorded when it's also generated for other targets
+    pub native_platform: Option<String>,
+    /// The expected output on the other targets, where it differs
+    #[serde(default)]
+    pub platform_outputs: Vec<PlatformOutput>,
+    /// The contents the program is expected to leave in its
+    /// `assert_tempdir_file`s, by path
+    #[serde(default)]
+    pub tempdir_files: Vec<(String, Vec<u8>)>,
+    /// What the test was generated with
+    #[serde(default)]
+    pub meta: Option<WasiTestMeta>,
+}
+
+/// What a test was generated with, for reproducing it. It isn't compared when
+/// checking the `.wast`s, as it changes with the machine generating them.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WasiTestMeta {
+    /// The first line of the native compiler's `--version`
+    pub native_compiler: Option<String>,
+    /// The first line of the Wasm compiler's `--version`, only known for the
+    /// `.wast` of a version
+    pub wasm_compiler: Option<String>,
+}
+
+/// The expected output of a test on a target whose native program outputs
+/// something else than the host's
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlatformOutput {
+    /// The target triple
+    pub target: String,
+    /// The output on stdout, if it differs
+    pub stdout: Option<String>,
+    /// The output on stderr, if it differs
+    pub stderr: Option<String>,
+}
+
+impl WasiTest {
+    /// The name of the sidecar file listing the expected filesystem effects
+    fn fs_effects_file_name(&self) -> String {
+        format!(
+            "{}.fs_effects.json",
+            self.wasm_prog_name.trim_end_matches(".wasm")
+        )
+    }
+
+    /// The test of `version`, with the directives scoped to it
+    pub fn for_version(&self, version: WasiVersion) -> WasiTest {
+        WasiTest {
+            options: self.options.for_version(version),
+            ..self.clone()
+        }
+    }
+
+    /// The `.wast` of the test, which `WasiTest::from_wast` reads back
+    pub fn into_wasi_wast(&self) -> String {
+        self.to_wast(false, self.meta.as_ref())
+    }
+
+    /// The `.wast` of the test, for a component rather than a core module if
+    /// `component`, with `meta` in place of the test's
+    fn to_wast(&self, component: bool, meta: Option<&WasiTestMeta>) -> String {
+        let mut out = format!(
+            ";; This file was generated by https://github.com/wasmerio/wasi-tests\n
+(wasi_test \"{}\"",
+            self.wasm_prog_name
+        );
+        if component {
+            out += "\n  (component)";
+        }
+        match self.options.xfail.as_deref() {
+            Some("") => out += "\n  (xfail)",
+            Some(reason) => out += &format!("\n  (xfail {})", util::wast_string(reason)),
+            None => (),
+        }
+        // the output isn't known, only the exit code is asserted
+        if self.options.no_native_run {
+            out += "\n  (no_native_run)";
+        }
+        // sorted, so that reordering the directives doesn't change the `.wast`.
+        // The sort is stable, so the last of the same var still wins.
+        let mut env = self.options.env.iter().collect::<Vec<_>>();
+        env.sort_by(|(a, _), (b, _)| a.cmp(b));
+        // the preopens too, unless their fds are asserted, which follow their order
+        let mut dir = self.options.dir.iter().collect::<Vec<_>>();
+        let mut mapdir = self.options.mapdir.iter().collect::<Vec<_>>();
+        if self.options.assert_preopens.is_none() {
+            dir.sort();
+            mapdir.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        if !env.is_empty() {
+            let envs = env
+                .iter()
+                .map(|(name, value)| util::wast_string(&format!("{}={}", name, value)))
+                .collect::<Vec<String>>()
+                .join(" ");
+            out += &format!("\n  (envs {})", envs);
+        }
+        if !self.options.args.is_empty() {
+            let args = self
+                .options
+                .args
+                .iter()
+                .map(|v| util::wast_string(v))
+                .collect::<Vec<String>>()
+                .join(" ");
+            out += &format!("\n  (args {})", args);
+        }
+
+        if !dir.is_empty() {
+            let preopens = dir
+                .iter()
+                .map(|v| util::wast_string(&v.path))
+                .collect::<Vec<String>>()
+                .join(" ");
+            out += &format!("\n  (preopens {})", preopens);
+        }
+        if !mapdir.is_empty() {
+            let map_dirs = mapdir
+                .iter()
+                .map(|(a, b)| util::wast_string(&format!("{}:{}", a, b)))
+                .collect::<Vec<String>>()
+                .join(" ");
+            out += &format!("\n  (map_dirs {})", map_dirs);
+        }
+        if let Some(cwd) = &self.options.cwd {
+            out += &format!("\n  (cwd {})", util::wast_string(cwd));
         }
         if !self.options.tempdir.is_empty() {
             let temp_dirs = self
@@ -333,106 +3203,1918 @@ impl WasiTest {
                 .join(" ");
             out += &format!("\n  (temp_dirs {})", temp_dirs);
         }
+        if !self.options.pre_clean.is_empty() {
+            let pre_clean = self
+                .options
+                .pre_clean
+                .iter()
+                .map(|path| util::wast_string(path))
+                .collect::<Vec<String>>()
+                .join(" ");
+            out += &format!("\n  (pre_clean {})", pre_clean);
+        }
+        if self.options.serialize_output {
+            out += "\n  (serialize_output)";
+        }
+        for pattern in &self.options.scrub {
+            out += &format!("\n  (scrub {})", util::wast_string(pattern));
+        }
+        if self.options.read_only {
+            out += "\n  (read_only_preopens)";
+        } else if dir.iter().any(|v| v.read_only) {
+            let read_only_dirs = dir
+                .iter()
+                .filter(|v| v.read_only)
+                .map(|v| util::wast_string(&v.path))
+                .collect::<Vec<String>>()
+                .join(" ");
+            out += &format!("\n  (read_only_dirs {})", read_only_dirs);
+        }
+        for sparse_file in &self.options.sparse_file {
+            out += &format!(
+                "\n  (sparse_file {} {} {})",
+                util::wast_string(&sparse_file.path),
+                sparse_file.offset,
+                util::wast_string(&sparse_file.marker)
+            );
+        }
+        if !self.options.random_tempdir.is_empty() {
+            let random_temp_dirs = self
+                .options
+                .random_tempdir
+                .iter()
+                .map(|env_var| util::wast_string(env_var))
+                .collect::<Vec<String>>()
+                .join(" ");
+            out += &format!("\n  (random_temp_dirs {})", random_temp_dirs);
+        }
+        if let Some(memory_limit) = self.options.memory_limit {
+            out += &format!("\n  (memory_limit {})", memory_limit);
+        }
+        if let Some(seed) = self.options.random_seed {
+            out += &format!("\n  (random_seed {})", seed);
+        }
+
+        match &self.options.trap {
+            Some(message) => out += &format!("\n  (assert_trap {})", util::wast_string(message)),
+            None => {
+                out += &format!("\n  (assert_return (i64.const {}))", self.result);
+                if let Some(name) = self
+                    .options
+                    .exit
+                    .as_ref()
+                    .and_then(|exit| exit.name.as_ref())
+                {
+                    out += &format!(" ;; {}", name);
+                }
+            }
+        }
+        if let Some(stdin) = &self.options.stdin {
+            out += &format!("\n  (stdin {})", util::wast_string(stdin));
+        } else if let Some(stdin) = &self.options.stdin_bytes {
+            out += &format!("\n  (stdin_bytes {})", util::wast_bytes(stdin));
+        }
+
+        if !self.options.stdout_contains.is_empty() {
+            for text in &self.options.stdout_contains {
+                out += &format!("\n  (assert_stdout_contains {})", util::wast_string(text));
+            }
+        } else if let Some(stdout) = &self.stdout_bytes {
+            out += &format!("\n  (assert_stdout_bytes {})", util::wast_bytes(stdout));
+        } else if !self.stdout.is_empty() || self.options.assert_empty_output {
+            out += &format!("\n  (assert_stdout {})", util::wast_string(&self.stdout));
+        }
+        // a panic message names the source file, which is compiled from a
+        // different path for each target
+        if self.options.trap.is_none() {
+            if !self.options.stderr_contains.is_empty() {
+                for text in &self.options.stderr_contains {
+                    out += &format!("\n  (assert_stderr_contains {})", util::wast_string(text));
+                }
+            } else if let Some(stderr) = &self.stderr_bytes {
+                out += &format!("\n  (assert_stderr_bytes {})", util::wast_bytes(stderr));
+            } else if !self.stderr.is_empty() || self.options.assert_empty_output {
+                out += &format!("\n  (assert_stderr {})", util::wast_string(&self.stderr));
+            }
+        }
+        if let Some(platform) = &self.native_platform {
+            out += &format!("\n  (native_platform {})", util::wast_string(platform));
+        }
+        for output in &self.platform_outputs {
+            let target = util::wast_string(&output.target);
+            if let Some(stdout) = &output.stdout {
+                out += &format!(
+                    "\n  (assert_stdout_on {} {})",
+                    target,
+                    util::wast_string(stdout)
+                );
+            }
+            if let Some(stderr) = &output.stderr {
+                out += &format!(
+                    "\n  (assert_stderr_on {} {})",
+                    target,
+                    util::wast_string(stderr)
+                );
+            }
+        }
+        for (path, len) in &self.options.assert_file_len {
+            out += &format!("\n  (assert_file_len {} {})", util::wast_string(path), len);
+        }
+        for path in self.options.expected_files() {
+            out += &format!("\n  (assert_exists {})", util::wast_string(path));
+        }
+        for (path, contents) in &self.tempdir_files {
+            out += &match std::str::from_utf8(contents) {
+                Ok(text) => format!(
+                    "\n  (assert_file {} {})",
+                    util::wast_string(path),
+                    util::wast_string(text)
+                ),
+                Err(_) => format!(
+                    "\n  (assert_file_bytes {} {})",
+                    util::wast_string(path),
+                    util::wast_bytes(contents)
+                ),
+            };
+        }
+        if self.options.assert_exit_matches_stdout {
+            out += "\n  (assert_exit_matches_stdout)";
+        }
+        if let Some(clock) = self.options.assert_clock {
+            out += &format!("\n  (assert_clock \"{}\")", clock.get_name());
+        }
+        if !self.options.record_fs_effects.is_empty() {
+            out += &format!("\n  (assert_fs_effects {:?})", self.fs_effects_file_name());
+        }
+        if self.options.assert_preopens.is_some() {
+            let preopens = self
+                .options
+                .expected_preopens()
+                .iter()
+                .map(|(fd, name)| format!("\"{}:{}\"", fd, name))
+                .collect::<Vec<String>>()
+                .join(" ");
+            out += &format!("\n  (assert_preopens {})", preopens);
+        }
+        if let Some(meta) = meta {
+            let mut clauses = vec![];
+            if let Some(compiler) = &meta.native_compiler {
+                clauses.push(format!("(native_compiler {})", util::wast_string(compiler)));
+            }
+            if let Some(compiler) = &meta.wasm_compiler {
+                clauses.push(format!("(wasm_compiler {})", util::wast_string(compiler)));
+            }
+            if !clauses.is_empty() {
+                out += &format!("\n  (meta {})", clauses.join(" "));
+            }
+        }
+
+        out += "\n)\n";
+
+        out
+    }
+}
+
+/// The options provied when executed a WASI Wasm program
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WasiOptions {
+    /// Mapped pre-opened dirs
+    pub mapdir: Vec<(String, String)>,
+    /// Environment vars
+    pub env: Vec<(String, String)>,
+    /// Files of `KEY=VALUE` lines, relative to the test file, whose vars are
+    /// added to `env`. The vars given by `env` take precedence.
+    pub envfile: Vec<String>,
+    /// Vars of the host environment added to `env` with their values when
+    /// generating. The vars given by `env` or `envfile`s take precedence.
+    pub passenv: Vec<String>,
+    /// Program arguments. Each `arg` directive gives one or more, split and
+    /// quoted like in a shell
+    pub args: Vec<String>,
+    /// Files of program arguments, relative to the test file, whose arguments
+    /// are added to `args` after the others. See `parse_arg_file`.
+    pub arg_file: Vec<String>,
+    /// Pre-opened directories
+    pub dir: Vec<Preopen>,
+    /// The directory the program runs in, relative to the directory the tests
+    /// run in. The Wasm program gets it mapped as `.`, which is what its
+    /// relative paths resolve against, so the native program's other paths
+    /// are relative to it too.
+    pub cwd: Option<String>,
+    /// Resolve the relative host paths of the `dir`s and `mapdir`s against the
+    /// directory of the test file rather than the directory the tests run in,
+    /// for tests with their fixtures next to them
+    pub dirs_relative_to_test: bool,
+    /// The alias of the temporary directory to use
+    pub tempdir: Vec<String>,
+    /// Env vars giving the program the path of a temporary directory with a
+    /// random name. The runner preopens it under that path and replaces
+    /// `{{ENV_VAR}}` in the expected output with it.
+    pub random_tempdir: Vec<String>,
+    /// Stdin to give to the native program and WASI program. The directive's
+    /// quoted value may use escapes like `\n` for multi-line input.
+    pub stdin: Option<String>,
+    /// Stdin to give to the programs when it isn't UTF-8, instead of `stdin`.
+    /// The directive, `stdin_base64`, gives it in base64.
+    pub stdin_bytes: Option<Vec<u8>>,
+    /// The fd of the first preopen, if the program must see exactly the
+    /// declared preopens
+    pub assert_preopens: Option<u32>,
+    /// Compare the output with its lines sorted, for programs whose lines are
+    /// deterministic but interleave unpredictably
+    pub serialize_output: bool,
+    /// Build the program natively but don't run it, for programs that can't
+    /// run on the host: the test only asserts that the Wasm program exits
+    /// with 0, not its output
+    pub no_native_run: bool,
+    /// Whether to turn the `\r\n`s of the native output into `\n`s, as
+    /// written by the Wasm program, overriding `BuildOptions`'. Off for tests
+    /// checking for `\r\n`.
+    pub normalize_line_endings: Option<bool>,
+    /// Sparse files to create before the program runs
+    pub sparse_file: Vec<SparseFile>,
+    /// Preopen everything without write rights. The native program runs with
+    /// the preopened directories made read-only.
+    pub read_only: bool,
+    /// Files or directories, relative to the directory the tests run in, to
+    /// remove before the program runs so it starts from a clean state
+    pub pre_clean: Vec<String>,
+    /// Trees, relative to the directory the tests run in, whose changes by
+    /// the program are recorded as the expected filesystem effects
+    pub record_fs_effects: Vec<String>,
+    /// Files, relative to the directory the tests run in, that the program
+    /// must have written with the given length in bytes
+    pub assert_file_len: Vec<(String, u64)>,
+    /// Files the program must write in its `tempdir`s, by the path it writes
+    /// them at, like `scratch/out.txt` for the `tempdir` `scratch`, whose
+    /// contents after the native run are asserted. The native program gets
+    /// their `tempdir`s as empty directories of the directory it runs in.
+    pub assert_tempdir_file: Vec<String>,
+    /// What the program must return besides its stdout and stderr: its exit
+    /// code, or files, relative to the directory the tests run in, that it
+    /// must have created
+    pub returns: Vec<ExpectedReturn>,
+    /// The exit code the test asserts, in place of the native program's, which
+    /// must agree with it unless it doesn't run, optionally with a name that
+    /// the `.wast` gives it
+    pub exit: Option<ExpectedExit>,
+    /// The program prints a number and must exit with that same number
+    pub assert_exit_matches_stdout: bool,
+    /// Assert the program's stdout and stderr even when they're empty, so that
+    /// it starting to print something fails the test
+    pub assert_empty_output: bool,
+    /// Texts the program's stdout must contain, asserted instead of its whole
+    /// stdout, for output with a varying part like a PID or a time
+    pub stdout_contains: Vec<String>,
+    /// Texts the program's stderr must contain, asserted instead of its whole
+    /// stderr
+    pub stderr_contains: Vec<String>,
+    /// Regexes whose matches in the output are replaced with `[scrubbed]`,
+    /// natively and by the runner, for unavoidably varying parts like a
+    /// random number, applied in order
+    pub scrub: Vec<String>,
+    /// The program must panic or abort, and the runner's trap message must
+    /// contain this text
+    pub trap: Option<String>,
+    /// The clock the program must query with `clock_time_get`. This can't be
+    /// observed natively, so it's only checked by the runner.
+    pub assert_clock: Option<WasiClock>,
+    /// Prebuilt crates, by name, to link the program against with `--extern`.
+    /// The path is relative to the directory the tests run in, with `{target}`
+    /// replaced by `native` or the WASI version's directory name.
+    pub externs: Vec<(String, String)>,
+    /// Build with overflow checks. By default the native build, which is
+    /// unoptimized, panics on overflow while the optimized Wasm build wraps.
+    pub overflow_checks: bool,
+    /// The `-C opt-level` to build with, for both the native and the Wasm
+    /// builds. By default the native build is unoptimized and the Wasm build
+    /// is optimized for size (`z`).
+    pub opt_level: Option<String>,
+    /// The Rust edition to build with, for both the native and the Wasm
+    /// builds. By default it's `rustc`'s, 2015.
+    pub edition: Option<String>,
+    /// Extra flags passed to `rustc` for both the native and the Wasm builds,
+    /// split and quoted like in a shell
+    pub rustflags: Vec<String>,
+    /// Extra flags passed to `rustc` for the Wasm builds only, like
+    /// `-C target-feature=+simd128`
+    pub wasm_rustflags: Vec<String>,
+    /// Extra arguments passed to the linker of the native build only, with
+    /// `-C link-arg=` for `rustc` and after the source for `cc`, like `-lm`
+    pub link_args: Vec<String>,
+    /// Close the native program's inherited fds from this one up before it
+    /// runs, as they won't exist under WASI (Unix only)
+    pub native_close_fds: Option<u32>,
+    /// Don't generate the test, for the given reason, which may be empty. For
+    /// tests the current toolchains can't build.
+    pub skip: Option<String>,
+    /// The Wasm program is known not to behave like the native one, for the
+    /// given reason, which may be empty. The test is generated all the same,
+    /// and the runner is expected to treat its failure as expected.
+    pub xfail: Option<String>,
+    /// The most linear memory the Wasm program may have, in bytes. Only the
+    /// runner enforces it, the native program runs without a limit.
+    pub memory_limit: Option<u64>,
+    /// The seed of the runner's `random_get`, also given to the program as
+    /// the `RANDOM_SEED_VAR` env var
+    pub random_seed: Option<u64>,
+    /// The only WASI functions the Wasm program may import, checked once it's
+    /// built when there are any. Components aren't checked.
+    pub imports: Vec<String>,
+    /// The directives scoped to a WASI version, by its directory name, that
+    /// its tests add to these. The native program runs without them.
+    pub by_version: BTreeMap<String, WasiOptions>,
+}
+
+/// A preopened directory, written as its path with a `:ro` suffix if it's
+/// read-only, or an optional `:rw` one otherwise
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct Preopen {
+    /// The path of the directory, relative to the directory the tests run in
+    pub path: String,
+    /// Preopen it without write rights. The native program runs with the
+    /// directory made read-only.
+    pub read_only: bool,
+}
+
+impl Preopen {
+    /// The read-write preopen of `path`, as is
+    pub fn new(path: String) -> Self {
+        Preopen {
+            path,
+            read_only: false,
+        }
+    }
+}
+
+impl From<String> for Preopen {
+    fn from(dir: String) -> Self {
+        if let Some(path) = dir.strip_suffix(":ro") {
+            Preopen {
+                path: path.to_string(),
+                read_only: true,
+            }
+        } else if let Some(path) = dir.strip_suffix(":rw") {
+            Preopen::new(path.to_string())
+        } else {
+            Preopen::new(dir)
+        }
+    }
+}
+
+impl From<Preopen> for String {
+    fn from(dir: Preopen) -> Self {
+        if dir.read_only {
+            format!("{}:ro", dir.path)
+        } else {
+            dir.path
+        }
+    }
+}
+
+/// A file that is all holes except for a marker at a (large) offset, so that
+/// high offsets can be tested without gigabytes of fixture data on disk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SparseFile {
+    /// The path of the file, relative to the directory the tests run in
+    pub path: String,
+    /// Where the marker is written
+    pub offset: u64,
+    /// The data at the end of the file
+    pub marker: String,
+}
+
+/// An expectation of a `returns` directive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ExpectedReturn {
+    /// `returns: N`, the exit code the program must have, which is only
+    /// checked natively as the generated test asserts the exit code anyway
+    ExitCode(i64),
+    /// `returns: exists path`, a file or directory the program must create
+    FileExists(String),
+}
+
+/// An `exit` directive: `NAME=code`, or only the code
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExpectedExit {
+    /// What the exit code means, like `EXIT_USAGE`
+    pub name: Option<String>,
+    pub code: i64,
+}
+
+impl fmt::Display for ExpectedExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}={}", name, self.code),
+            None => write!(f, "{}", self.code),
+        }
+    }
+}
+
+/// The clocks a WASI program can query
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WasiClock {
+    Realtime,
+    Monotonic,
+    ProcessCputime,
+    ThreadCputime,
+}
+
+impl WasiClock {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "realtime" => Some(WasiClock::Realtime),
+            "monotonic" => Some(WasiClock::Monotonic),
+            "process_cputime" => Some(WasiClock::ProcessCputime),
+            "thread_cputime" => Some(WasiClock::ThreadCputime),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            WasiClock::Realtime => "realtime",
+            WasiClock::Monotonic => "monotonic",
+            WasiClock::ProcessCputime => "process_cputime",
+            WasiClock::ThreadCputime => "thread_cputime",
+        }
+    }
+}
+
+/// The fd WASI runtimes conventionally give to the first preopen, after stdio.
+pub(crate) const DEFAULT_FIRST_PREOPEN_FD: u32 = 3;
+
+/// The unit the linear memory of a Wasm module grows by
+const WASM_PAGE_SIZE: u64 = 64 << 10;
+
+/// The levels `-C opt-level` takes
+const OPT_LEVELS: &[&str] = &["0", "1", "2", "3", "s", "z"];
+
+/// The editions `--edition` takes
+const EDITIONS: &[&str] = &["2015", "2018", "2021", "2024"];
+
+/// What `{target}` is replaced with in the paths of the native build's externs
+pub(crate) const NATIVE_EXTERN_TARGET: &str = "native";
+
+impl WasiOptions {
+    /// Merges the options of `other` into these, `other`'s taking precedence:
+    /// the lists are extended with the entries they don't have yet (the
+    /// arguments and `rustc` flags are appended as is), `other`'s env vars,
+    /// mapped directories and extern crates replace those with the same name,
+    /// its values replace those set here, and the flags are set if either sets
+    /// them.
+    pub fn merge(&mut self, other: &WasiOptions) {
+        merge_by_key(&mut self.mapdir, &other.mapdir);
+        merge_by_key(&mut self.env, &other.env);
+        union(&mut self.envfile, &other.envfile);
+        union(&mut self.arg_file, &other.arg_file);
+        union(&mut self.passenv, &other.passenv);
+        self.args.extend(other.args.iter().cloned());
+        // the rights of a directory given by both are `other`'s
+        for dir in &other.dir {
+            match self.dir.iter_mut().find(|d| d.path == dir.path) {
+                Some(d) => *d = dir.clone(),
+                None => self.dir.push(dir.clone()),
+            }
+        }
+        if other.cwd.is_some() {
+            self.cwd = other.cwd.clone();
+        }
+        self.dirs_relative_to_test |= other.dirs_relative_to_test;
+        union(&mut self.tempdir, &other.tempdir);
+        union(&mut self.random_tempdir, &other.random_tempdir);
+        // either stdin replaces the other
+        if other.stdin.is_some() || other.stdin_bytes.is_some() {
+            self.stdin = other.stdin.clone();
+            self.stdin_bytes = other.stdin_bytes.clone();
+        }
+        self.assert_preopens = other.assert_preopens.or(self.assert_preopens);
+        self.serialize_output |= other.serialize_output;
+        self.no_native_run |= other.no_native_run;
+        self.normalize_line_endings = other.normalize_line_endings.or(self.normalize_line_endings);
+        union(&mut self.sparse_file, &other.sparse_file);
+        self.read_only |= other.read_only;
+        union(&mut self.pre_clean, &other.pre_clean);
+        union(&mut self.record_fs_effects, &other.record_fs_effects);
+        merge_by_key(&mut self.assert_file_len, &other.assert_file_len);
+        union(&mut self.assert_tempdir_file, &other.assert_tempdir_file);
+        union(&mut self.returns, &other.returns);
+        if other.exit.is_some() {
+            self.exit = other.exit.clone();
+        }
+        self.assert_exit_matches_stdout |= other.assert_exit_matches_stdout;
+        self.assert_empty_output |= other.assert_empty_output;
+        union(&mut self.stdout_contains, &other.stdout_contains);
+        union(&mut self.stderr_contains, &other.stderr_contains);
+        union(&mut self.scrub, &other.scrub);
+        if other.trap.is_some() {
+            self.trap = other.trap.clone();
+        }
+        self.assert_clock = other.assert_clock.or(self.assert_clock);
+        merge_by_key(&mut self.externs, &other.externs);
+        self.overflow_checks |= other.overflow_checks;
+        if other.opt_level.is_some() {
+            self.opt_level = other.opt_level.clone();
+        }
+        if other.edition.is_some() {
+            self.edition = other.edition.clone();
+        }
+        self.rustflags.extend(other.rustflags.iter().cloned());
+        self.wasm_rustflags
+            .extend(other.wasm_rustflags.iter().cloned());
+        self.link_args.extend(other.link_args.iter().cloned());
+        self.native_close_fds = other.native_close_fds.or(self.native_close_fds);
+        if other.skip.is_some() {
+            self.skip = other.skip.clone();
+        }
+        if other.xfail.is_some() {
+            self.xfail = other.xfail.clone();
+        }
+        self.memory_limit = other.memory_limit.or(self.memory_limit);
+        self.random_seed = other.random_seed.or(self.random_seed);
+        union(&mut self.imports, &other.imports);
+        for (version, scoped) in &other.by_version {
+            self.by_version
+                .entry(version.clone())
+                .or_default()
+                .merge(scoped);
+        }
+    }
 
-        out += &format!("\n  (assert_return (i64.const {}))", self.result);
-        if let Some(stdin) = &self.options.stdin {
-            out += &format!("\n  (stdin {:?})", stdin);
+    /// The options of the tests of `version`: these, with the directives
+    /// scoped to it merged in
+    pub fn for_version(&self, version: WasiVersion) -> WasiOptions {
+        let mut options = self.clone();
+        options.by_version.clear();
+        if let Some(scoped) = self.by_version.get(version.get_directory_name()) {
+            options.merge(scoped);
         }
+        options
+    }
+
+    /// The path of each extern crate for the build of `target`, with the tests
+    /// run in `execute_dir`
+    fn extern_paths<'a>(
+        &'a self,
+        target: &'a str,
+        execute_dir: &'a Path,
+    ) -> impl Iterator<Item = (&'a str, PathBuf)> {
+        self.externs.iter().map(move |(name, path)| {
+            let path = path.replace("{target}", target);
+            (name.as_str(), execute_dir.join(path))
+        })
+    }
+
+    /// The path of the first extern crate that doesn't exist for the build of `target`
+    fn missing_extern(&self, target: &str, execute_dir: &Path) -> Option<PathBuf> {
+        self.extern_paths(target, execute_dir)
+            .map(|(_, path)| path)
+            .find(|path| !path.exists())
+    }
 
-        if !self.stdout.is_empty() {
-            out += &format!("\n  (assert_stdout {:?})", self.stdout);
+    /// The extra `rustc` flags used to build the program for `target`, either
+    /// native or a WASI version's directory name, with the tests run in
+    /// `execute_dir`
+    pub(crate) fn rustc_flags(&self, target: &str, execute_dir: &Path) -> Vec<String> {
+        let mut flags = vec![];
+        for (name, path) in self.extern_paths(target, execute_dir) {
+            flags.push("--extern".to_string());
+            flags.push(format!("{}={}", name, path.to_string_lossy()));
+        }
+        if self.overflow_checks {
+            flags.push("-C".to_string());
+            flags.push("overflow-checks=on".to_string());
+        }
+        if let Some(opt_level) = &self.opt_level {
+            flags.push("-C".to_string());
+            flags.push(format!("opt-level={}", opt_level));
+        }
+        if let Some(edition) = &self.edition {
+            flags.push(format!("--edition={}", edition));
         }
-        if !self.stderr.is_empty() {
-            out += &format!("\n  (assert_stderr {:?})", self.stderr);
+        flags.extend(self.rustflags.iter().cloned());
+        if target == NATIVE_EXTERN_TARGET {
+            for link_arg in &self.link_args {
+                flags.push("-C".to_string());
+                flags.push(format!("link-arg={}", link_arg));
+            }
+        } else {
+            flags.extend(self.wasm_rustflags.iter().cloned());
         }
+        flags
+    }
 
-        out += "\n)\n";
+    /// The extra C compiler flags used to build the program, native or not.
+    /// The options only meaningful to `rustc` are left out.
+    pub(crate) fn c_flags(&self) -> Vec<String> {
+        self.opt_level
+            .iter()
+            .map(|opt_level| format!("-O{}", opt_level))
+            .collect()
+    }
 
-        out
+    /// The first of the options that are asserted from the native run, if the
+    /// program isn't run with `no_native_run`
+    fn native_run_assertion(&self) -> Option<&'static str> {
+        if !self.no_native_run {
+            return None;
+        }
+        [
+            ("trap", self.trap.is_some()),
+            ("returns", !self.returns.is_empty()),
+            ("stdout_contains", !self.stdout_contains.is_empty()),
+            ("stderr_contains", !self.stderr_contains.is_empty()),
+            ("assert_empty_output", self.assert_empty_output),
+            (
+                "assert_exit_matches_stdout",
+                self.assert_exit_matches_stdout,
+            ),
+            ("assert_preopens", self.assert_preopens.is_some()),
+            ("assert_tempdir_file", !self.assert_tempdir_file.is_empty()),
+            ("record_fs_effects", !self.record_fs_effects.is_empty()),
+        ]
+        .iter()
+        .find(|(_, set)| *set)
+        .map(|(name, _)| *name)
+    }
+
+    /// Checks the directives that can't go together, once the options files
+    /// and the defaults are merged
+    fn check(&self) -> Result<(), String> {
+        self.scrub_regexes()?;
+        if let Some(name) = self.native_run_assertion() {
+            return Err(format!(
+                "`{}` needs the native run, which `no_native_run` skips",
+                name
+            ));
+        }
+        if self.exit.is_some() && self.trap.is_some() {
+            return Err("`exit` can't go with `trap`, which has no exit code".to_string());
+        }
+        if let Some(path) = self
+            .assert_tempdir_file
+            .iter()
+            .find(|path| self.split_tempdir_path(path).is_none())
+        {
+            return Err(format!(
+                "`assert_tempdir_file` `{}` isn't in a `tempdir`",
+                path
+            ));
+        }
+        Ok(())
+    }
+
+    /// The paths of the files the program must create, from `returns`
+    fn expected_files(&self) -> impl Iterator<Item = &String> {
+        self.returns.iter().filter_map(|expected| match expected {
+            ExpectedReturn::FileExists(path) => Some(path),
+            ExpectedReturn::ExitCode(_) => None,
+        })
+    }
+
+    /// Makes the relative host paths of the `dir`s, `mapdir`s and `cwd`, which are
+    /// relative to `test_dir`, relative to `execute_dir` instead, as the native
+    /// run and the runner resolve them against the directory the tests run in.
+    /// They're made absolute if `test_dir` isn't in it, and absolute paths are
+    /// kept as they are.
+    fn resolve_dirs_against(&mut self, test_dir: &Path, execute_dir: &Path) {
+        let resolve = |dir: &mut String| {
+            if Path::new(dir.as_str()).is_absolute() {
+                return;
+            }
+            let path = test_dir.join(dir.as_str());
+            *dir = path
+                .strip_prefix(execute_dir)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+        };
+        self.dir.iter_mut().for_each(|dir| resolve(&mut dir.path));
+        self.mapdir.iter_mut().for_each(|(_, dir)| resolve(dir));
+        self.cwd.iter_mut().for_each(resolve);
+        for scoped in self.by_version.values_mut() {
+            scoped.resolve_dirs_against(test_dir, execute_dir);
+        }
+    }
+
+    /// The `tempdir` holding `path`, one of the `assert_tempdir_file`s, and
+    /// the path of the file in it. A named `tempdir` is preferred to `.`.
+    fn split_tempdir_path<'a>(&'a self, path: &'a str) -> Option<(&'a str, &'a Path)> {
+        let (current, named): (Vec<_>, Vec<_>) = self.tempdir.iter().partition(|dir| *dir == ".");
+        named
+            .into_iter()
+            .find_map(|dir| {
+                Path::new(path)
+                    .strip_prefix(dir)
+                    .ok()
+                    .map(|in_dir| (dir.as_str(), in_dir))
+            })
+            .or_else(|| current.first().map(|dir| (dir.as_str(), Path::new(path))))
+            .filter(|(_, in_dir)| !in_dir.as_os_str().is_empty())
+    }
+
+    /// The compiled `scrub` patterns, or what's wrong with the first invalid one
+    fn scrub_regexes(&self) -> Result<Vec<Regex>, String> {
+        self.scrub
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| format!("invalid `scrub` `{}`: {}", pattern, e))
+            })
+            .collect()
+    }
+
+    /// The stdin to give to the programs, from `stdin` or `stdin_bytes`
+    fn stdin_input(&self) -> Option<&[u8]> {
+        self.stdin
+            .as_deref()
+            .map(str::as_bytes)
+            .or(self.stdin_bytes.as_deref())
+    }
+
+    /// The directory the program runs in
+    fn program_dir(&self, execute_dir: &Path) -> PathBuf {
+        match &self.cwd {
+            Some(cwd) => execute_dir.join(cwd),
+            None => execute_dir.to_path_buf(),
+        }
+    }
+
+    /// Adds the arguments of the `arg_file`s, resolved against `test_dir`, to
+    /// the `args`
+    fn load_arg_files(&mut self, test_dir: &Path) -> Result<(), WasiTestError> {
+        for arg_file in &self.arg_file {
+            let path = test_dir.join(arg_file);
+            let contents = fs::read_to_string(&path).map_err(WasiTestError::io(&path))?;
+            self.args.extend(parse_arg_file(&contents));
+        }
+        Ok(())
+    }
+
+    /// Adds the vars of the `envfile`s, resolved against `test_dir`, to the
+    /// `env`, whose own vars override them
+    fn load_envfiles(&mut self, test_dir: &Path) -> Result<(), WasiTestError> {
+        let mut env = vec![];
+        for envfile in &self.envfile {
+            let path = test_dir.join(envfile);
+            let contents = fs::read_to_string(&path).map_err(WasiTestError::io(&path))?;
+            let vars = parse_envfile(&contents).map_err(|message| WasiTestError::Parse {
+                file: path.clone(),
+                message,
+            })?;
+            merge_by_key(&mut env, &vars);
+        }
+        merge_by_key(&mut env, &self.env);
+        self.env = env;
+        Ok(())
+    }
+
+    /// Adds the `passenv` vars, with their values in the host environment, to
+    /// the `env`, whose own vars override them
+    fn load_passenv(&mut self) -> Result<(), String> {
+        let mut env = vec![];
+        for name in &self.passenv {
+            let value = std::env::var(name)
+                .map_err(|_| format!("`passenv` var `{}` isn't set in the environment", name))?;
+            // the value ends up in a `.wast` that's likely to be committed
+            warn!(
+                "Writing the value of the host's `{}` to the `.wast`: make sure it isn't a secret",
+                name
+            );
+            env.push((name.clone(), value));
+        }
+        merge_by_key(&mut env, &self.env);
+        self.env = env;
+        Ok(())
+    }
+
+    /// The host paths of the preopened `dir`s, `mapdir`s and `cwd`
+    fn preopen_host_dirs(&self) -> impl Iterator<Item = &String> {
+        self.dir
+            .iter()
+            .map(|dir| &dir.path)
+            .chain(self.mapdir.iter().map(|(_, real_dir)| real_dir))
+            .chain(self.cwd.iter())
+    }
+
+    /// The host paths of the preopens without write rights: all of them with
+    /// `read_only`, and the read-only `dir`s otherwise
+    fn read_only_host_dirs(&self) -> impl Iterator<Item = &String> {
+        let read_only = self.read_only;
+        self.preopen_host_dirs()
+            .zip(
+                self.dir
+                    .iter()
+                    .map(|dir| dir.read_only)
+                    .chain(std::iter::repeat(false)),
+            )
+            .filter(move |(_, read_only_dir)| read_only || *read_only_dir)
+            .map(|(dir, _)| dir)
+    }
+
+    /// The `(fd, name)` of every preopen the program should see, numbered from
+    /// the `assert_preopens` fd in the order `dir`s, `mapdir`s, `cwd` (as `.`),
+    /// `tempdir`s.
+    fn expected_preopens(&self) -> Vec<(u32, String)> {
+        let first_fd = self.assert_preopens.unwrap_or(DEFAULT_FIRST_PREOPEN_FD);
+        self.dir
+            .iter()
+            .map(|dir| dir.path.as_str())
+            .chain(self.mapdir.iter().map(|(alias, _)| alias.as_str()))
+            .chain(self.cwd.iter().map(|_| "."))
+            .chain(self.tempdir.iter().map(String::as_str))
+            .enumerate()
+            .map(|(i, name)| (first_fd + i as u32, name.to_string()))
+            .collect()
     }
 }
 
-/// The options provied when executed a WASI Wasm program
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct WasiOptions {
-    /// Mapped pre-opened dirs
-    pub mapdir: Vec<(String, String)>,
-    /// Environment vars
-    pub env: Vec<(String, String)>,
-    /// Program arguments
-    pub args: Vec<String>,
-    /// Pre-opened directories
-    pub dir: Vec<String>,
-    /// The alias of the temporary directory to use
-    pub tempdir: Vec<String>,
-    /// Stdin to give to the native program and WASI program.
-    pub stdin: Option<String>,
+/// Parses the `<fd>: <name>` lines a program prints to list its preopens.
+fn listed_preopens(stdout: &str) -> Vec<(u32, String)> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let colon_idx = line.find(": ")?;
+            let fd = line[..colon_idx].parse::<u32>().ok()?;
+            Some((fd, line[colon_idx + 2..].to_string()))
+        })
+        .collect()
+}
+
+/// The path of the `<test>.wasi.toml` holding options for the test in `file`
+fn sidecar_path(file: &str) -> PathBuf {
+    Path::new(file).with_extension("wasi.toml")
+}
+
+/// Reads the sidecar options file of the test in `file`, if it has one
+fn read_sidecar(file: &str) -> Result<Option<String>, WasiTestError> {
+    let path = sidecar_path(file);
+    match fs::read_to_string(&path) {
+        Ok(sidecar) => {
+            debug!("Reading options from `{}`", path.to_string_lossy());
+            Ok(Some(sidecar))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(WasiTestError::io(&path)(e)),
+    }
+}
+
+/// The options of the test in `file`, whose source is `src_code`: those of its
+/// `// WASI:` header, overridden by those of its `sidecar` options file, then
+/// merged into the `defaults`. With `dirs_relative_to_test`, its directories
+/// are resolved against `test_dir`, the directory of the test file, rather
+/// than `execute_dir`.
+fn test_options(
+    file: &str,
+    src_code: &str,
+    sidecar: Option<&str>,
+    defaults: &TestDefaults,
+    test_dir: &Path,
+    execute_dir: &Path,
+) -> Result<WasiOptions, WasiTestError> {
+    let mut options = extract_args_from_source_file(src_code)
+        .map_err(|message| WasiTestError::Parse {
+            file: PathBuf::from(file),
+            message,
+        })?
+        .unwrap_or_default();
+    if let Some(sidecar) = sidecar {
+        options = apply_sidecar(options, sidecar).map_err(|message| WasiTestError::Parse {
+            file: sidecar_path(file),
+            message,
+        })?;
+    }
+    if defaults.toml.is_some() {
+        let mut merged = defaults.options.clone();
+        merged.merge(&options);
+        options = merged;
+    }
+    let parse_error = |message: String| WasiTestError::Parse {
+        file: PathBuf::from(file),
+        message,
+    };
+    options.load_envfiles(test_dir)?;
+    options.load_arg_files(test_dir)?;
+    options.load_passenv().map_err(parse_error)?;
+    // the Wasm program's, after the env files so that they don't override it
+    if let Some(seed) = options.random_seed {
+        merge_by_key(
+            &mut options.env,
+            &[(RANDOM_SEED_VAR.to_string(), seed.to_string())],
+        );
+    }
+    // the versions of the options files can be any of their names
+    for (name, mut scoped) in std::mem::take(&mut options.by_version) {
+        let version = name
+            .parse::<WasiVersion>()
+            .map_err(|e| parse_error(format!("{}", e)))?;
+        scoped.load_envfiles(test_dir)?;
+        scoped.load_arg_files(test_dir)?;
+        scoped.load_passenv().map_err(parse_error)?;
+        // replacing the unscoped seed's var when the versions are merged
+        if let Some(seed) = scoped.random_seed {
+            merge_by_key(
+                &mut scoped.env,
+                &[(RANDOM_SEED_VAR.to_string(), seed.to_string())],
+            );
+        }
+        options
+            .by_version
+            .entry(version.get_directory_name().to_string())
+            .or_default()
+            .merge(&scoped);
+    }
+    if options.dirs_relative_to_test {
+        options.resolve_dirs_against(test_dir, execute_dir);
+    }
+    // the patterns of the options files and the defaults aren't checked yet,
+    // nor are the scoped directives merged with the unscoped ones
+    options.check().map_err(parse_error)?;
+    for name in options.by_version.keys() {
+        let version = name
+            .parse::<WasiVersion>()
+            .map_err(|e| parse_error(format!("{}", e)))?;
+        options
+            .for_version(version)
+            .check()
+            .map_err(|message| parse_error(format!("for `{}`: {}", name, message)))?;
+    }
+    // checked before anything is generated, as nothing builds them
+    if options.skip.is_none() {
+        let targets = std::iter::once((NATIVE_EXTERN_TARGET, options.clone())).chain(
+            WasiVersion::all()
+                .iter()
+                .map(|version| (version.get_directory_name(), options.for_version(*version))),
+        );
+        for (target, options) in targets {
+            if let Some(missing) = options.missing_extern(target, execute_dir) {
+                return Err(parse_error(format!(
+                    "extern crate at `{}` is missing, see `wasi/test_fs/externs/build.sh`",
+                    missing.to_string_lossy()
+                )));
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// The arguments of an argument file, taken as they are: separated by NULs if
+/// it has any, and one per line otherwise, a `\r\n` ending a line like a `\n`.
+/// Either way, the separator ending the file doesn't start another argument,
+/// but the others do, even if it's empty, so an empty line is an empty
+/// argument.
+fn parse_arg_file(contents: &str) -> Vec<String> {
+    if contents.contains('\0') {
+        let contents = contents.strip_suffix('\0').unwrap_or(contents);
+        contents.split('\0').map(str::to_string).collect()
+    } else {
+        contents.lines().map(str::to_string).collect()
+    }
+}
+
+/// The vars of an env file: `KEY=VALUE` lines, optionally starting with
+/// `export`, with blank lines and `#` comments ignored. Like with the `env`
+/// directive, only the first `=` separates the name, and a value quoted with
+/// `"` or `'` is unquoted.
+fn parse_envfile(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => (name.trim(), value.trim()),
+            _ => return Err(format!("line {}: expected `KEY=VALUE`: `{}`", i + 1, line)),
+        };
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|&quote| value.strip_prefix(quote)?.strip_suffix(quote))
+            .unwrap_or(value);
+        vars.push((name.to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+/// Where the options every test starts from are read, if it exists
+const WASI_TEST_DEFAULTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/wasi/tests/_defaults.toml");
+
+/// The options of `WASI_TEST_DEFAULTS`, which the options of every test are
+/// merged into
+#[derive(Debug, Default)]
+struct TestDefaults {
+    options: WasiOptions,
+    /// The file's contents, which the tests are generated from too, or `None`
+    /// if there's no such file
+    toml: Option<String>,
+}
+
+/// Reads the `WASI_TEST_DEFAULTS`, which are empty if the file doesn't exist
+fn read_test_defaults() -> Result<TestDefaults, WasiTestError> {
+    let path = Path::new(WASI_TEST_DEFAULTS);
+    let toml = match fs::read_to_string(path) {
+        Ok(toml) => toml,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(TestDefaults::default()),
+        Err(e) => return Err(WasiTestError::io(path)(e)),
+    };
+    debug!(
+        "Reading the default options from `{}`",
+        path.to_string_lossy()
+    );
+    let options = toml::from_str(&toml).map_err(|e| WasiTestError::Parse {
+        file: path.to_path_buf(),
+        message: e.to_string(),
+    })?;
+    Ok(TestDefaults {
+        options,
+        toml: Some(toml),
+    })
+}
+
+/// Overrides the `options` with those of the TOML `sidecar`, whose keys are
+/// the names of the fields of `WasiOptions`. A key replaces the whole field,
+/// so a list in the sidecar replaces the one of the header rather than
+/// extending it.
+fn apply_sidecar(options: WasiOptions, sidecar: &str) -> Result<WasiOptions, String> {
+    let sidecar: toml::Table = toml::from_str(sidecar).map_err(|e| e.to_string())?;
+    let mut merged = match toml::Value::try_from(&options).map_err(|e| e.to_string())? {
+        toml::Value::Table(table) => table,
+        other => unreachable!("options serialized as {:?}", other),
+    };
+    merged.extend(sidecar);
+    toml::Value::Table(merged)
+        .try_into()
+        .map_err(|e| e.to_string())
+}
+
+/// Appends the entries of `other` that `list` doesn't have yet
+fn union<T: Clone + PartialEq>(list: &mut Vec<T>, other: &[T]) {
+    for entry in other {
+        if !list.contains(entry) {
+            list.push(entry.clone());
+        }
+    }
+}
+
+/// Sets the entries of `other` in `list`, replacing those with the same key
+/// in place and appending the others
+fn merge_by_key<T: Clone>(list: &mut Vec<(String, T)>, other: &[(String, T)]) {
+    for (key, value) in other {
+        match list.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value.clone(),
+            None => list.push((key.clone(), value.clone())),
+        }
+    }
+}
+
+/// The index of the `// WASI:` line starting the header of `source_code`, if
+/// it has one. It may follow a shebang, crate-level attributes, comments and
+/// blank lines, but no code, so that a `// WASI:` further down isn't taken for
+/// it. Only attributes on a single line are seen.
+fn wasi_header_line(source_code: &str) -> Option<usize> {
+    for (i, line) in source_code.lines().enumerate() {
+        if line.starts_with("// WASI:") {
+            return Some(i);
+        }
+        let line = line.trim();
+        let before_code = line.is_empty()
+            || line.starts_with("//")
+            || line.starts_with("#![")
+            || (i == 0 && line.starts_with("#!"));
+        if !before_code {
+            return None;
+        }
+    }
+    None
+}
+
+/// The text of a directive's value: as is, or in double quotes with the
+/// escapes of `stdin`, for text starting or ending with spaces
+fn directive_text(value: &str) -> Result<String, String> {
+    match value
+        .strip_prefix('"')
+        .and_then(|quoted| quoted.strip_suffix('"'))
+    {
+        Some(quoted) => util::unescape_directive(quoted),
+        None => Ok(value.to_string()),
+    }
 }
 
 /// Pulls args to the program out of a comment at the top of the file starting with "// WasiOptions:"
-fn extract_args_from_source_file(source_code: &str) -> Option<WasiOptions> {
-    if source_code.starts_with("// WASI:") {
-        let mut args = WasiOptions::default();
+///
+/// Returns an error describing the first malformed directive.
+fn extract_args_from_source_file(source_code: &str) -> Result<Option<WasiOptions>, String> {
+    if let Some(header_line) = wasi_header_line(source_code) {
+        let mut options = WasiOptions::default();
         for arg_line in source_code
             .lines()
-            .skip(1)
+            .skip(header_line + 1)
             .take_while(|line| line.starts_with("// "))
         {
             let arg_line = arg_line.strip_prefix("// ").unwrap();
             let arg_line = arg_line.trim();
-            let colon_idx = arg_line
-                .find(':')
-                .expect("directives provided at the top must be separated by a `:`");
-
-            let (command_name, value) = arg_line.split_at(colon_idx);
-            let value = value.strip_prefix(':').unwrap();
-            let value = value.trim();
+            let (args, arg_line) = match split_version_scope(arg_line)? {
+                Some((version, directive)) => (
+                    options
+                        .by_version
+                        .entry(version.get_directory_name().to_string())
+                        .or_default(),
+                    directive,
+                ),
+                None => (&mut options, arg_line),
+            };
+            let (command_name, value) = match arg_line.split_once(':') {
+                Some((command_name, value)) => (command_name, value.trim()),
+                // the only directives that can go without a value
+                None if ["skip", "xfail", "no_native_run", "no-native-run"].contains(&arg_line) => {
+                    (arg_line, "")
+                }
+                None => {
+                    return Err(format!(
+                        "directives provided at the top must be separated by a `:`: `{}`",
+                        arg_line
+                    ))
+                }
+            };
 
-            match command_name.as_ref() {
+            match command_name {
                 "mapdir" => {
-                    // We try first splitting by `::`
-                    if let [alias, real_dir] = value.split("::").collect::<Vec<&str>>()[..] {
-                        args.mapdir.push((alias.to_string(), real_dir.to_string()));
-                    } else
-                    // And then we try splitting by `:` (for compatibility with previous API)
-                    if let [alias, real_dir] = value.split(':').collect::<Vec<&str>>()[..] {
-                        args.mapdir.push((alias.to_string(), real_dir.to_string()));
-                    } else {
-                        eprintln!("Parse error in mapdir {} not parsed correctly", value);
+                    // Only the first separator splits, so that the real dir can
+                    // have colons, like `C:\data`. We try first splitting by `::`,
+                    // unless a `:` comes before it
+                    let (alias, real_dir) = match value.split_once("::") {
+                        Some((alias, real_dir)) if !alias.contains(':') => (alias, real_dir),
+                        // And then we try splitting by `:` (for compatibility with previous API)
+                        _ => value
+                            .split_once(':')
+                            .ok_or_else(|| format!("Parse error in mapdir: `{}`", value))?,
+                    };
+                    // the runner splits `alias:real_dir` at the first colon
+                    if alias.contains(':') {
+                        return Err(format!("mapdir alias `{}` can't contain a `:`", alias));
                     }
+                    args.mapdir.push((alias.to_string(), real_dir.to_string()));
                 }
                 "env" => {
-                    if let [name, val] = value.split('=').collect::<Vec<&str>>()[..] {
-                        args.env.push((name.to_string(), val.to_string()));
-                    } else {
-                        eprintln!("Parse error in env {} not parsed correctly", value);
-                    }
+                    // only the first `=` separates the name, values can have some
+                    let (name, val) = value
+                        .split_once('=')
+                        .ok_or_else(|| format!("Parse error in env: `{}`", value))?;
+                    args.env.push((name.to_string(), val.to_string()));
+                }
+                "cwd" => {
+                    args.cwd = Some(value.to_string());
+                }
+                "envfile" => {
+                    args.envfile.push(value.to_string());
+                }
+                "arg_file" | "arg-file" => {
+                    args.arg_file.push(value.to_string());
+                }
+                "passenv" => {
+                    args.passenv.push(value.to_string());
                 }
                 "dir" => {
-                    args.dir.push(value.to_string());
+                    args.dir.push(Preopen::from(value.to_string()));
                 }
                 "arg" => {
-                    args.args.push(value.to_string());
+                    let words = util::split_shell_words(value)
+                        .map_err(|e| format!("Parse error in arg: {}", e))?;
+                    args.args.extend(words);
                 }
                 "tempdir" => {
                     args.tempdir.push(value.to_string());
                 }
+                "random_tempdir" => {
+                    args.random_tempdir.push(value.to_string());
+                }
                 "stdin" => {
-                    assert!(args.stdin.is_none(), "Only the first `stdin` directive is used! Please correct this or update this code");
+                    if args.stdin.is_some() || args.stdin_bytes.is_some() {
+                        return Err("only one `stdin` directive is supported".to_string());
+                    }
                     let s = value;
-                    let s = s.strip_prefix('"').expect("expected leading '\"' in stdin");
+                    let s = s
+                        .strip_prefix('"')
+                        .ok_or("expected leading '\"' in stdin")?;
                     let s = s
                         .trim_end()
                         .strip_suffix("\"")
-                        .expect("expected trailing '\"' in stdin");
-                    args.stdin = Some(s.to_string());
+                        .ok_or("expected trailing '\"' in stdin")?;
+                    args.stdin = Some(
+                        util::unescape_directive(s)
+                            .map_err(|e| format!("Parse error in stdin: {}", e))?,
+                    );
+                }
+                "stdin_base64" | "stdin-base64" => {
+                    if args.stdin.is_some() || args.stdin_bytes.is_some() {
+                        return Err("only one `stdin` directive is supported".to_string());
+                    }
+                    args.stdin_bytes = Some(
+                        util::decode_base64(value)
+                            .map_err(|e| format!("Parse error in stdin_base64: {}", e))?,
+                    );
+                }
+                "assert_preopens" => {
+                    args.assert_preopens = Some(if value.is_empty() {
+                        DEFAULT_FIRST_PREOPEN_FD
+                    } else {
+                        value
+                            .parse()
+                            .map_err(|_| "`assert_preopens` takes the fd of the first preopen")?
+                    });
+                }
+                "normalize_line_endings" => {
+                    args.normalize_line_endings = Some(
+                        value
+                            .parse()
+                            .map_err(|_| "`normalize_line_endings` must be `true` or `false`")?,
+                    );
+                }
+                "serialize_output" => {
+                    args.serialize_output = value
+                        .parse()
+                        .map_err(|_| "`serialize_output` must be `true` or `false`")?;
+                }
+                "no_native_run" | "no-native-run" => {
+                    args.no_native_run = value.is_empty()
+                        || value
+                            .parse()
+                            .map_err(|_| "`no_native_run` must be `true` or `false`")?;
+                }
+                "sparse_file" => {
+                    // `path @ offset => marker`
+                    let (path, offset, marker) = value
+                        .split_once('@')
+                        .and_then(|(path, rest)| {
+                            rest.split_once("=>")
+                                .map(|(offset, marker)| (path, offset, marker))
+                        })
+                        .ok_or_else(|| {
+                            format!(
+                                "Parse error in sparse_file, expected `path @ offset => marker`: `{}`",
+                                value
+                            )
+                        })?;
+                    args.sparse_file.push(SparseFile {
+                        path: path.trim().to_string(),
+                        offset: util::parse_size(offset)
+                            .map_err(|e| format!("Parse error in the sparse_file offset: {}", e))?,
+                        marker: marker.trim().to_string(),
+                    });
+                }
+                "dirs_relative_to_test" => {
+                    args.dirs_relative_to_test = value
+                        .parse()
+                        .map_err(|_| "`dirs_relative_to_test` must be `true` or `false`")?;
+                }
+                "read_only" => {
+                    args.read_only = value
+                        .parse()
+                        .map_err(|_| "`read_only` must be `true` or `false`")?;
+                }
+                "assert_tempdir_file" => {
+                    args.assert_tempdir_file.push(value.to_string());
+                }
+                "pre_clean" => {
+                    args.pre_clean.push(value.to_string());
+                }
+                "record_fs_effects" => {
+                    args.record_fs_effects.push(value.to_string());
+                }
+                "assert_file_len" => {
+                    let (path, len) = match value.split("=>").collect::<Vec<&str>>()[..] {
+                        [path, len] => (path, len),
+                        _ => {
+                            return Err(format!(
+                                "Parse error in assert_file_len, expected `path => len`: `{}`",
+                                value
+                            ))
+                        }
+                    };
+                    let len = util::parse_size(len)
+                        .map_err(|e| format!("Parse error in assert_file_len: {}", e))?;
+                    args.assert_file_len.push((path.trim().to_string(), len));
+                }
+                "returns" => {
+                    let expected =
+                        if let Some(path) = value.strip_prefix("exists ") {
+                            ExpectedReturn::FileExists(path.trim().to_string())
+                        } else {
+                            ExpectedReturn::ExitCode(value.parse().map_err(|_| {
+                                "`returns` takes an exit code or `exists` and a path"
+                            })?)
+                        };
+                    args.returns.push(expected);
+                }
+                "exit" => {
+                    let malformed = || format!("`exit` takes `NAME=code` or a code: `{}`", value);
+                    let (name, code) = match value.split_once('=') {
+                        Some((name, code)) => (Some(name.trim()), code.trim()),
+                        None => (None, value),
+                    };
+                    let name = match name {
+                        Some(name) if name.is_empty() || name.contains(char::is_whitespace) => {
+                            return Err(malformed())
+                        }
+                        name => name.map(str::to_string),
+                    };
+                    let code = code.parse().map_err(|_| malformed())?;
+                    args.exit = Some(ExpectedExit { name, code });
+                }
+                "trap" => {
+                    args.trap = Some(value.to_string());
+                }
+                "assert_exit_matches_stdout" => {
+                    args.assert_exit_matches_stdout = value
+                        .parse()
+                        .map_err(|_| "`assert_exit_matches_stdout` must be `true` or `false`")?;
+                }
+                "assert_empty_output" => {
+                    args.assert_empty_output = value
+                        .parse()
+                        .map_err(|_| "`assert_empty_output` must be `true` or `false`")?;
+                }
+                "stdout_contains" | "stdout-contains" => {
+                    args.stdout_contains.push(directive_text(value)?);
+                }
+                "stderr_contains" | "stderr-contains" => {
+                    args.stderr_contains.push(directive_text(value)?);
+                }
+                "scrub" => {
+                    Regex::new(value).map_err(|e| format!("invalid `scrub` `{}`: {}", value, e))?;
+                    args.scrub.push(value.to_string());
+                }
+                "assert_clock" => {
+                    args.assert_clock = Some(WasiClock::from_name(value).ok_or(
+                        "`assert_clock` must be one of `realtime`, `monotonic`, `process_cputime` or `thread_cputime`",
+                    )?);
+                }
+                "extern" => {
+                    let (name, path) = value
+                        .split_once('=')
+                        .ok_or_else(|| format!("Parse error in extern: `{}`", value))?;
+                    args.externs
+                        .push((name.trim().to_string(), path.trim().to_string()));
+                }
+                "overflow_checks" => {
+                    args.overflow_checks = value
+                        .parse()
+                        .map_err(|_| "`overflow_checks` must be `true` or `false`")?;
+                }
+                "opt_level" | "opt-level" => {
+                    if !OPT_LEVELS.contains(&value) {
+                        return Err(format!(
+                            "`opt_level` must be one of {}, not `{}`",
+                            OPT_LEVELS
+                                .iter()
+                                .map(|level| format!("`{}`", level))
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                            value
+                        ));
+                    }
+                    args.opt_level = Some(value.to_string());
+                }
+                "edition" => {
+                    if !EDITIONS.contains(&value) {
+                        return Err(format!(
+                            "`edition` must be one of {}, not `{}`",
+                            EDITIONS
+                                .iter()
+                                .map(|edition| format!("`{}`", edition))
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                            value
+                        ));
+                    }
+                    args.edition = Some(value.to_string());
+                }
+                "rustflags" => {
+                    let flags = util::split_shell_words(value)
+                        .map_err(|e| format!("Parse error in rustflags: {}", e))?;
+                    args.rustflags.extend(flags);
+                }
+                "wasm_rustflags" | "wasm-rustflags" => {
+                    let flags = util::split_shell_words(value)
+                        .map_err(|e| format!("Parse error in wasm_rustflags: {}", e))?;
+                    args.wasm_rustflags.extend(flags);
+                }
+                "link_arg" | "link-arg" => {
+                    args.link_args.push(value.to_string());
+                }
+                "native_close_fds" => {
+                    let first_fd = if value.is_empty() {
+                        3
+                    } else {
+                        value
+                            .parse()
+                            .map_err(|_| "`native_close_fds` takes the first fd to close")?
+                    };
+                    if first_fd < 3 {
+                        return Err("`native_close_fds` must keep stdio (fds 0-2) open".to_string());
+                    }
+                    args.native_close_fds = Some(first_fd);
+                }
+                "skip" => {
+                    args.skip = Some(value.to_string());
+                }
+                "xfail" => {
+                    args.xfail = Some(value.to_string());
+                }
+                "memory" => {
+                    let limit = util::parse_size(value)
+                        .map_err(|e| format!("Parse error in memory: {}", e))?;
+                    if limit % WASM_PAGE_SIZE != 0 {
+                        return Err(format!(
+                            "`memory` must be a whole number of 64KiB Wasm pages: `{}`",
+                            value
+                        ));
+                    }
+                    args.memory_limit = Some(limit);
+                }
+                "seed" => {
+                    args.random_seed = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("`seed` takes a number: `{}`", value))?,
+                    );
+                }
+                "imports" => {
+                    for import in value.split(|c: char| c == ',' || c.is_whitespace()) {
+                        if import.is_empty() {
+                            continue;
+                        }
+                        if !WASI_FUNCTIONS.contains(&import) {
+                            return Err(format!(
+                                "`imports` takes the names of WASI functions, like `fd_write`: `{}`",
+                                import
+                            ));
+                        }
+                        args.imports.push(import.to_string());
+                    }
                 }
                 e => {
-                    eprintln!("WARN: comment arg: `{}` is not supported", e);
+                    warn!("comment arg: `{}` is not supported", e);
+                }
+            }
+        }
+        return Ok(Some(options));
+    }
+    Ok(None)
+}
+
+/// The version and the directive of a `WASI(<version>): <directive>` line,
+/// whose directive only applies to the tests of that version
+fn split_version_scope(line: &str) -> Result<Option<(WasiVersion, &str)>, String> {
+    let scoped = match line.strip_prefix("WASI(") {
+        Some(scoped) => scoped,
+        None => return Ok(None),
+    };
+    let (version, directive) = scoped
+        .split_once("):")
+        .ok_or_else(|| format!("expected `WASI(<version>): <directive>`: `{}`", line))?;
+    let version = version.trim().parse().map_err(|e| format!("{}", e))?;
+    Ok(Some((version, directive.trim())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_argv_is_the_wast_args() {
+        let file = concat!(env!("CARGO_MANIFEST_DIR"), "/wasi/tests/args.rs");
+        let options = extract_args_from_source_file(&fs::read_to_string(file).unwrap())
+            .unwrap()
+            .unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output = generate_native_output(
+            temp_dir.path(),
+            file,
+            "args",
+            &options.args,
+            &options,
+            &BuildOptions::default(),
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        // the program prints each argument it gets debug-formatted
+        let argv = output
+            .stdout
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let expected = options
+            .args
+            .iter()
+            .map(|arg| format!("{:?}", arg))
+            .collect::<Vec<_>>();
+        assert_eq!(argv, expected);
+
+        let test = WasiTest {
+            options,
+            stdout: output.stdout,
+            ..WasiTest::default()
+        };
+        let wast_args = WasiTest::from_wast(&test.into_wasi_wast())
+            .unwrap()
+            .options
+            .args;
+        assert_eq!(wast_args, test.options.args);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fds_from_the_first_are_marked_cloexec() {
+        use std::os::unix::io::AsRawFd;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = fs::File::create(temp_dir.path().join("fd")).unwrap();
+        let fd = file.as_raw_fd();
+        let cloexec = || unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC != 0;
+        unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+        cloexec_fds_from(fd as u32 + 1).unwrap();
+        assert!(!cloexec());
+        cloexec_fds_from(fd as u32).unwrap();
+        assert!(cloexec());
+    }
+
+    #[test]
+    fn missing_extern_is_an_error() {
+        let source = "// WASI:\n// extern: greeting=externs/{target}/libgreeting.rlib\n";
+        let execute_dir = tempfile::tempdir().unwrap();
+        let options = |execute_dir: &Path| {
+            test_options(
+                "greet.rs",
+                source,
+                None,
+                &TestDefaults::default(),
+                execute_dir,
+                execute_dir,
+            )
+        };
+        let error = options(execute_dir.path()).unwrap_err().to_string();
+        assert!(error.contains("libgreeting.rlib"), "{}", error);
+        let targets = WasiVersion::all()
+            .iter()
+            .map(|version| version.get_directory_name())
+            .chain(std::iter::once(NATIVE_EXTERN_TARGET));
+        for target in targets {
+            let dir = execute_dir.path().join("externs").join(target);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("libgreeting.rlib"), "").unwrap();
+        }
+        assert!(options(execute_dir.path()).is_ok());
+    }
+
+    #[test]
+    fn version_scoped_seed_sets_its_env_var() {
+        let source = "// WASI:\n// seed: 1\n// WASI(snapshot1): seed: 5\n";
+        let options = test_options(
+            "seed.rs",
+            source,
+            None,
+            &TestDefaults::default(),
+            Path::new("."),
+            Path::new("."),
+        )
+        .unwrap();
+        let seed_var = |options: &WasiOptions| {
+            options
+                .env
+                .iter()
+                .filter(|(name, _)| name == RANDOM_SEED_VAR)
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>()
+        };
+        let snapshot1 = options.for_version(WasiVersion::Snapshot1);
+        assert_eq!(snapshot1.random_seed, Some(5));
+        assert_eq!(seed_var(&snapshot1), ["5"]);
+        let unstable = options.for_version(WasiVersion::Unstable);
+        assert_eq!(unstable.random_seed, Some(1));
+        assert_eq!(seed_var(&unstable), ["1"]);
+        let wast = WasiTest {
+            options: snapshot1,
+            ..WasiTest::default()
+        }
+        .into_wasi_wast();
+        assert!(
+            wast.contains(&format!("\"{}=5\"", RANDOM_SEED_VAR)),
+            "{}",
+            wast
+        );
+    }
+
+    #[test]
+    fn scoped_directives_merge_with_unscoped_ones() {
+        let source = "// WASI:
+// env: A=1
+// env: B=2
+// arg: base
+// dir: shared
+// WASI(snapshot1): env: B=3
+// WASI(snapshot1): arg: extra
+// WASI(snapshot1): dir: extra
+// WASI(snapshot1): cwd: sub
+";
+        let options = extract_args_from_source_file(source).unwrap().unwrap();
+        let env = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let dirs = |options: &WasiOptions| {
+            options
+                .dir
+                .iter()
+                .map(|dir| dir.path.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let snapshot1 = options.for_version(WasiVersion::Snapshot1);
+        // overridden
+        assert_eq!(snapshot1.env, env(&[("A", "1"), ("B", "3")]));
+        assert_eq!(snapshot1.cwd.as_deref(), Some("sub"));
+        // appended
+        assert_eq!(snapshot1.args, ["base", "extra"]);
+        assert_eq!(dirs(&snapshot1), ["shared", "extra"]);
+
+        // unscoped only
+        let unstable = options.for_version(WasiVersion::Unstable);
+        assert_eq!(unstable.env, env(&[("A", "1"), ("B", "2")]));
+        assert_eq!(unstable.cwd, None);
+        assert_eq!(unstable.args, ["base"]);
+        assert_eq!(dirs(&unstable), ["shared"]);
+    }
+
+    #[test]
+    fn scoped_directives_are_checked_with_unscoped_ones() {
+        let options = |source: &str, defaults: &TestDefaults| {
+            test_options(
+                "scoped.rs",
+                source,
+                None,
+                defaults,
+                Path::new("."),
+                Path::new("."),
+            )
+        };
+        let no_defaults = TestDefaults::default();
+        // the header's patterns are checked as they're parsed, unlike these
+        let mut scoped_scrub = WasiOptions::default();
+        scoped_scrub
+            .by_version
+            .entry("snapshot1".to_string())
+            .or_default()
+            .scrub
+            .push("(unclosed".to_string());
+        let scoped_scrub = TestDefaults {
+            options: scoped_scrub,
+            toml: Some(String::new()),
+        };
+        let invalid = [
+            (
+                "// WASI:\n// exit: 3\n// WASI(snapshot1): trap: unreachable\n",
+                &no_defaults,
+            ),
+            (
+                "// WASI:\n// no_native_run\n// WASI(snapshot1): stdout_contains: ok\n",
+                &no_defaults,
+            ),
+            (
+                "// WASI:\n// WASI(snapshot1): assert_tempdir_file: scratch/out.txt\n",
+                &no_defaults,
+            ),
+            ("", &scoped_scrub),
+        ];
+        for (source, defaults) in invalid {
+            let error = options(source, defaults).unwrap_err().to_string();
+            assert!(error.contains("for `snapshot1`"), "{}", error);
+        }
+        let valid = "// WASI:\n// exit: 3\n// WASI(snapshot1): exit: 4\n";
+        assert!(options(valid, &no_defaults).is_ok());
+    }
+
+    #[test]
+    fn native_program_runs_in_the_execute_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let execute_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(execute_dir.path().join("data")).unwrap();
+        fs::write(execute_dir.path().join("data/greeting.txt"), "hello\n").unwrap();
+        let file = temp_dir.path().join("read_relative.rs");
+        let source = "// WASI:
+// dir: data
+fn main() {
+    print!(\"{}\", std::fs::read_to_string(\"data/greeting.txt\").unwrap());
+}
+";
+        fs::write(&file, source).unwrap();
+        let options = extract_args_from_source_file(source).unwrap().unwrap();
+        let build_options = BuildOptions {
+            execute_dir: execute_dir.path().to_path_buf(),
+            ..BuildOptions::default()
+        };
+        let output = generate_native_output(
+            temp_dir.path(),
+            file.to_str().unwrap(),
+            "read_relative",
+            &options.args,
+            &options,
+            &build_options,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(output.stdout, "hello\n");
+    }
+
+    #[test]
+    fn with_features_declares_the_missing_features() {
+        let cases = [
+            // already declared
+            (
+                "#![feature(wasi_ext)]\nfn main() {}\n",
+                "#![feature(wasi_ext)]\nfn main() {}\n",
+            ),
+            (
+                "#![cfg_attr(target_os = \"wasi\", feature(wasi_ext))]\nfn main() {}\n",
+                "#![cfg_attr(target_os = \"wasi\", feature(wasi_ext))]\nfn main() {}\n",
+            ),
+            // merged into the existing attribute
+            (
+                "// WASI:\n#![feature(never_type)]\nfn main() {}\n",
+                "// WASI:\n#![feature(never_type, wasi_ext)]\nfn main() {}\n",
+            ),
+            // after the shebang
+            (
+                "#!/usr/bin/env run-cargo-script\nfn main() {}\n",
+                "#!/usr/bin/env run-cargo-script\n#![feature(wasi_ext)]\nfn main() {}\n",
+            ),
+            ("fn main() {}\n", "#![feature(wasi_ext)]\nfn main() {}\n"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(with_features(source, &["wasi_ext"]), expected);
+        }
+    }
+
+    #[test]
+    fn no_test_file_is_only_an_error_with_require_tests() {
+        let output_root = tempfile::tempdir().unwrap();
+        // every test file is excluded, as if there were none
+        let build_options = BuildOptions {
+            exclude: vec![glob::Pattern::new("*").unwrap()],
+            output_root: Some(output_root.path().to_path_buf()),
+            skip_post_processing: true,
+            ..BuildOptions::default()
+        };
+        assert!(build(&[], &build_options).is_ok());
+        let build_options = BuildOptions {
+            require_tests: true,
+            ..build_options
+        };
+        assert!(matches!(
+            build(&[], &build_options),
+            Err(WasiTestError::NoTests { .. })
+        ));
+        assert_eq!(fs::read_dir(output_root.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn versions_compiled_at_once_get_their_own_sources() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = "fn main() {}\n";
+        let versions = [WasiVersion::Snapshot1, WasiVersion::Preview2];
+        let written = std::thread::scope(|scope| {
+            let handles = versions
+                .iter()
+                .map(|&version| {
+                    let temp_dir = temp_dir.path();
+                    scope.spawn(move || {
+                        write_wasm_source(temp_dir, source, "hello", Language::Rust, version)
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap().unwrap())
+                .collect::<Vec<_>>()
+        });
+        let (snapshot1_source, snapshot1_wasm) = &written[0];
+        let (preview2_source, preview2_wasm) = &written[1];
+        assert_ne!(snapshot1_source, preview2_source);
+        assert_ne!(snapshot1_wasm, preview2_wasm);
+        assert_eq!(
+            fs::read_to_string(snapshot1_source).unwrap(),
+            "#![feature(wasi_ext)]\nfn main() {}\n"
+        );
+        assert_eq!(fs::read_to_string(preview2_source).unwrap(), source);
+    }
+
+    #[test]
+    fn output_settings_cover_what_changes_the_output() {
+        let default = BuildOptions::default();
+        let changed = [
+            BuildOptions {
+                skip_post_processing: !default.skip_post_processing,
+                ..default.clone()
+            },
+            BuildOptions {
+                normalize_line_endings: !default.normalize_line_endings,
+                ..default.clone()
+            },
+            BuildOptions {
+                execute_dir: PathBuf::from("elsewhere"),
+                ..default.clone()
+            },
+        ];
+        for options in &changed {
+            assert_ne!(options.output_settings(), default.output_settings());
+        }
+        let unrelated = BuildOptions {
+            force: true,
+            keep_going: true,
+            ..default.clone()
+        };
+        assert_eq!(unrelated.output_settings(), default.output_settings());
+    }
+
+    #[test]
+    fn env_value_keeps_its_equals_signs() {
+        let options = extract_args_from_source_file("// WASI:\n// env: TOKEN=a=b=c\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.env, [("TOKEN".to_string(), "a=b=c".to_string())]);
+    }
+
+    #[test]
+    fn mapdir_host_path_keeps_its_colons() {
+        for (directive, alias, host) in [
+            (r"guest:C:\Users\me\data", "guest", r"C:\Users\me\data"),
+            ("guest::C:/data", "guest", "C:/data"),
+            ("guest:/srv/a::b", "guest", "/srv/a::b"),
+            ("a:b::c", "a", "b::c"),
+        ] {
+            let source = format!("// WASI:\n// mapdir: {}\n", directive);
+            let options = extract_args_from_source_file(&source).unwrap().unwrap();
+            assert_eq!(
+                options.mapdir,
+                [(alias.to_string(), host.to_string())],
+                "{:?}",
+                directive
+            );
+        }
+    }
+
+    #[test]
+    fn env_order_does_not_change_the_wast() {
+        let wast = |source: &str| {
+            WasiTest {
+                wasm_prog_name: "env.wasm".to_string(),
+                options: extract_args_from_source_file(source).unwrap().unwrap(),
+                ..WasiTest::default()
+            }
+            .into_wasi_wast()
+        };
+        assert_eq!(
+            wast("// WASI:\n// env: B=2\n// env: A=1\n// env: C=3\n"),
+            wast("// WASI:\n// env: C=3\n// env: A=1\n// env: B=2\n")
+        );
+    }
+
+    #[test]
+    fn malformed_directives_are_errors() {
+        for directive in [
+            "mapdir: guest",
+            "env: TOKEN",
+            "extern: wasi_unstable",
+            "sparse_file: big.bin => marker",
+            "sparse_file: big.bin @ 1GiB",
+            "assert_file_len: big.bin",
+        ] {
+            let source = format!("// WASI:\n// {}\nfn main() {{}}\n", directive);
+            assert!(
+                extract_args_from_source_file(&source).is_err(),
+                "{:?}",
+                directive
+            );
+        }
+    }
+
+    #[test]
+    fn fixtures_parse() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/wasi/tests");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "rs") {
+                let source = fs::read_to_string(&path).unwrap();
+                if let Err(e) = extract_args_from_source_file(&source) {
+                    panic!("{}: {}", path.display(), e);
                 }
             }
         }
-        return Some(args);
     }
-    None
 }
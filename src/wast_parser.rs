@@ -0,0 +1,412 @@
+//! Reading a `.wast` back into the `WasiTest` it was generated from, the
+//! inverse of `WasiTest::into_wasi_wast`.
+//!
+//! Only what the `.wast` records can be read back. Left to their defaults are:
+//!
+//! - the options that only affect the generation: `extern`,
+//!   `overflow_checks`, `opt_level`, `edition`, the `rustflags`, `link_arg`s,
+//!   `native_close_fds`, `normalize_line_endings`, the allowed `imports` and
+//!   the trees of `record_fs_effects`
+//! - `dirs_relative_to_test`, whose directories are read back resolved
+//! - the `envfile`s and `passenv` vars, whose values are read back as `env`
+//! - the `arg_file`s, whose arguments are read back as `args`
+//! - the exit codes of `returns` and the `exit` directive, whose code is read
+//!   back as the result
+//! - the output and result of a trapping program
+//! - the output asserted with `stdout_contains` or `stderr_contains`
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+use wast::lexer::{Lexer, Source, Token};
+
+use super::wasitests::{
+    ExpectedReturn, PlatformOutput, Preopen, SparseFile, WasiClock, WasiTest, WasiTestMeta,
+    DEFAULT_FIRST_PREOPEN_FD,
+};
+
+/// The error of reading a `.wast` that isn't a `wasi_test`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid wasi_test: {}", self.0)
+    }
+}
+
+impl Error for ParseError {}
+
+fn error<T>(message: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError(message.into()))
+}
+
+#[derive(Debug)]
+enum SExpr<'a> {
+    List(Vec<SExpr<'a>>),
+    Keyword(&'a str),
+    String(Cow<'a, [u8]>),
+    Integer(&'a str),
+}
+
+/// Reads the s-expressions of `wast`, skipping comments
+fn read_sexprs(wast: &str) -> Result<Vec<SExpr<'_>>, ParseError> {
+    // the lists being read, innermost last
+    let mut stack: Vec<Vec<SExpr<'_>>> = vec![vec![]];
+    for source in Lexer::new(wast) {
+        let token = match source.map_err(|e| ParseError(e.to_string()))? {
+            Source::Token(token) => token,
+            Source::Comment(_) | Source::Whitespace(_) => continue,
+        };
+        let sexpr = match token {
+            Token::LParen(_) => {
+                stack.push(vec![]);
+                continue;
+            }
+            Token::RParen(_) => {
+                if stack.len() == 1 {
+                    return error("unbalanced `)`");
+                }
+                SExpr::List(stack.pop().unwrap())
+            }
+            Token::Keyword(keyword) => SExpr::Keyword(keyword),
+            Token::String { val, .. } => SExpr::String(val),
+            Token::Integer(integer) => SExpr::Integer(integer.src()),
+            other => return error(format!("unexpected `{}`", other.src())),
+        };
+        stack.last_mut().unwrap().push(sexpr);
+    }
+    if stack.len() != 1 {
+        return error("unclosed `(`");
+    }
+    Ok(stack.pop().unwrap())
+}
+
+fn string(sexpr: &SExpr<'_>) -> Result<String, ParseError> {
+    match sexpr {
+        SExpr::String(bytes) => String::from_utf8(bytes.to_vec())
+            .or_else(|_| error(format!("{:?} isn't UTF-8", String::from_utf8_lossy(bytes)))),
+        other => error(format!("expected a string, found {:?}", other)),
+    }
+}
+
+fn strings(args: &[SExpr<'_>]) -> Result<Vec<String>, ParseError> {
+    args.iter().map(string).collect()
+}
+
+fn bytes(sexpr: &SExpr<'_>) -> Result<Vec<u8>, ParseError> {
+    match sexpr {
+        SExpr::String(bytes) => Ok(bytes.to_vec()),
+        other => error(format!("expected a string, found {:?}", other)),
+    }
+}
+
+fn integer<T: std::str::FromStr>(sexpr: &SExpr<'_>) -> Result<T, ParseError> {
+    match sexpr {
+        SExpr::Integer(src) => src
+            .parse()
+            .or_else(|_| error(format!("`{}` is out of range", src))),
+        other => error(format!("expected an integer, found {:?}", other)),
+    }
+}
+
+/// The only argument of the clause `name`
+fn single<'s, 'a>(name: &str, args: &'s [SExpr<'a>]) -> Result<&'s SExpr<'a>, ParseError> {
+    match args {
+        [arg] => Ok(arg),
+        _ => error(format!("`{}` takes one argument", name)),
+    }
+}
+
+/// Splits `s` at its first `separator`
+fn split_pair(clause: &str, s: &str, separator: char) -> Result<(String, String), ParseError> {
+    match s.split_once(separator) {
+        Some((a, b)) => Ok((a.to_string(), b.to_string())),
+        None => error(format!("`{}` expects `{}` in {:?}", clause, separator, s)),
+    }
+}
+
+impl WasiTest {
+    /// Reads the test `into_wasi_wast` wrote to `wast`.
+    pub fn from_wast(wast: &str) -> Result<WasiTest, ParseError> {
+        let sexprs = read_sexprs(wast)?;
+        let (name, clauses) = match &sexprs[..] {
+            [SExpr::List(items)] => match &items[..] {
+                [SExpr::Keyword("wasi_test"), name, clauses @ ..] => (name, clauses),
+                _ => return error("expected `(wasi_test \"name.wasm\" ...)`"),
+            },
+            _ => return error("expected a single `(wasi_test ...)`"),
+        };
+        let mut test = WasiTest {
+            wasm_prog_name: string(name)?,
+            ..WasiTest::default()
+        };
+        test.read_clauses(clauses)?;
+        Ok(test)
+    }
+
+    fn read_clauses(&mut self, clauses: &[SExpr<'_>]) -> Result<(), ParseError> {
+        let options = &mut self.options;
+        for clause in clauses {
+            let (name, args) = match clause {
+                SExpr::List(items) => match &items[..] {
+                    [SExpr::Keyword(name), args @ ..] => (*name, args),
+                    _ => return error(format!("expected a clause, found {:?}", clause)),
+                },
+                _ => return error(format!("expected a clause, found {:?}", clause)),
+            };
+            match name {
+                "envs" => {
+                    for env in strings(args)? {
+                        options.env.push(split_pair(name, &env, '=')?);
+                    }
+                }
+                "args" => options.args = strings(args)?,
+                // as is, as a path may end like a rights suffix
+                "preopens" => options.dir = strings(args)?.into_iter().map(Preopen::new).collect(),
+                "map_dirs" => {
+                    for map_dir in strings(args)? {
+                        options.mapdir.push(split_pair(name, &map_dir, ':')?);
+                    }
+                }
+                "cwd" => options.cwd = Some(string(single(name, args)?)?),
+                "temp_dirs" => options.tempdir = strings(args)?,
+                "pre_clean" => options.pre_clean = strings(args)?,
+                "serialize_output" => options.serialize_output = true,
+                "no_native_run" => options.no_native_run = true,
+                "scrub" => options.scrub.push(string(single(name, args)?)?),
+                "read_only_preopens" => options.read_only = true,
+                // after the `preopens`, which are written first
+                "read_only_dirs" => {
+                    for path in strings(args)? {
+                        match options.dir.iter_mut().find(|dir| dir.path == path) {
+                            Some(dir) => dir.read_only = true,
+                            None => return error(format!("{:?} isn't preopened", path)),
+                        }
+                    }
+                }
+                "sparse_file" => match args {
+                    [path, offset, marker] => options.sparse_file.push(SparseFile {
+                        path: string(path)?,
+                        offset: integer(offset)?,
+                        marker: string(marker)?,
+                    }),
+                    _ => return error("`sparse_file` takes a path, an offset and a marker"),
+                },
+                "random_temp_dirs" => options.random_tempdir = strings(args)?,
+                "memory_limit" => options.memory_limit = Some(integer(single(name, args)?)?),
+                "random_seed" => options.random_seed = Some(integer(single(name, args)?)?),
+                "assert_return" => match single(name, args)? {
+                    SExpr::List(items) => match &items[..] {
+                        [SExpr::Keyword("i64.const"), result] => self.result = integer(result)?,
+                        _ => return error("`assert_return` takes an `(i64.const ...)`"),
+                    },
+                    _ => return error("`assert_return` takes an `(i64.const ...)`"),
+                },
+                "xfail" => {
+                    options.xfail = Some(match args {
+                        [] => String::new(),
+                        [reason] => string(reason)?,
+                        _ => return error("`xfail` takes at most a reason"),
+                    })
+                }
+                "assert_trap" => options.trap = Some(string(single(name, args)?)?),
+                "stdin" => options.stdin = Some(string(single(name, args)?)?),
+                "stdin_bytes" => options.stdin_bytes = Some(bytes(single(name, args)?)?),
+                // an empty output is only asserted with `assert_empty_output`
+                "assert_stdout" | "assert_stderr" => {
+                    let output = string(single(name, args)?)?;
+                    options.assert_empty_output |= output.is_empty();
+                    if name == "assert_stdout" {
+                        self.stdout = output;
+                    } else {
+                        self.stderr = output;
+                    }
+                }
+                "assert_stdout_contains" => {
+                    options.stdout_contains.push(string(single(name, args)?)?)
+                }
+                "assert_stderr_contains" => {
+                    options.stderr_contains.push(string(single(name, args)?)?)
+                }
+                "native_platform" => self.native_platform = Some(string(single(name, args)?)?),
+                "assert_stdout_on" | "assert_stderr_on" => {
+                    let (target, output) = match args {
+                        [target, output] => (string(target)?, string(output)?),
+                        _ => return error(format!("`{}` takes a target and an output", name)),
+                    };
+                    let index = match self
+                        .platform_outputs
+                        .iter()
+                        .position(|o| o.target == target)
+                    {
+                        Some(index) => index,
+                        None => {
+                            self.platform_outputs.push(PlatformOutput {
+                                target,
+                                ..PlatformOutput::default()
+                            });
+                            self.platform_outputs.len() - 1
+                        }
+                    };
+                    let platform_output = &mut self.platform_outputs[index];
+                    if name == "assert_stdout_on" {
+                        platform_output.stdout = Some(output);
+                    } else {
+                        platform_output.stderr = Some(output);
+                    }
+                }
+                "assert_stdout_bytes" => {
+                    let stdout = bytes(single(name, args)?)?;
+                    self.stdout = String::from_utf8_lossy(&stdout).to_string();
+                    self.stdout_bytes = Some(stdout);
+                }
+                "assert_stderr_bytes" => {
+                    let stderr = bytes(single(name, args)?)?;
+                    self.stderr = String::from_utf8_lossy(&stderr).to_string();
+                    self.stderr_bytes = Some(stderr);
+                }
+                "assert_file_len" => match args {
+                    [path, len] => options.assert_file_len.push((string(path)?, integer(len)?)),
+                    _ => return error("`assert_file_len` takes a path and a length"),
+                },
+                "assert_file" | "assert_file_bytes" => {
+                    let (path, contents) = match args {
+                        [path, contents] if name == "assert_file" => {
+                            (string(path)?, string(contents)?.into_bytes())
+                        }
+                        [path, contents] => (string(path)?, bytes(contents)?),
+                        _ => return error(format!("`{}` takes a path and contents", name)),
+                    };
+                    options.assert_tempdir_file.push(path.clone());
+                    self.tempdir_files.push((path, contents));
+                }
+                "assert_exists" => options
+                    .returns
+                    .push(ExpectedReturn::FileExists(string(single(name, args)?)?)),
+                "assert_exit_matches_stdout" => options.assert_exit_matches_stdout = true,
+                "assert_clock" => {
+                    let clock = string(single(name, args)?)?;
+                    options.assert_clock = Some(
+                        WasiClock::from_name(&clock)
+                            .ok_or_else(|| ParseError(format!("unknown clock `{}`", clock)))?,
+                    );
+                }
+                // the mark of the `.wast`s of component versions, which is the
+                // version's, not the test's
+                "component" => (),
+                // the effects are in the sidecar file, and the recorded trees
+                // aren't in the `.wast`
+                "assert_fs_effects" => (),
+                "assert_preopens" => {
+                    // the preopens themselves follow from the other options
+                    let first_fd = match strings(args)?.first() {
+                        Some(preopen) => split_pair(name, preopen, ':')?
+                            .0
+                            .parse()
+                            .or_else(|_| error(format!("invalid fd in {:?}", preopen)))?,
+                        None => DEFAULT_FIRST_PREOPEN_FD,
+                    };
+                    options.assert_preopens = Some(first_fd);
+                }
+                "meta" => {
+                    let meta = self.meta.get_or_insert_with(WasiTestMeta::default);
+                    for arg in args {
+                        match arg {
+                            SExpr::List(items) => match &items[..] {
+                                [SExpr::Keyword("native_compiler"), compiler] => {
+                                    meta.native_compiler = Some(string(compiler)?)
+                                }
+                                [SExpr::Keyword("wasm_compiler"), compiler] => {
+                                    meta.wasm_compiler = Some(string(compiler)?)
+                                }
+                                // what later generators record
+                                _ => (),
+                            },
+                            _ => {
+                                return error(format!("expected a `meta` clause, found {:?}", arg))
+                            }
+                        }
+                    }
+                }
+                _ => return error(format!("unknown clause `{}`", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasitests::WasiOptions;
+
+    #[test]
+    fn from_wast_reads_back_into_wasi_wast() {
+        let tests = vec![
+            WasiTest {
+                wasm_prog_name: "empty.wasm".to_string(),
+                ..WasiTest::default()
+            },
+            WasiTest {
+                wasm_prog_name: "options.wasm".to_string(),
+                stdout: "out \"quoted\"\nline\n".to_string(),
+                stderr: "err\ttab\n".to_string(),
+                result: 3,
+                options: WasiOptions {
+                    // written sorted by name
+                    env: vec![
+                        ("EMPTY".to_string(), "".to_string()),
+                        ("TOKEN".to_string(), "a=b=c".to_string()),
+                    ],
+                    args: vec![
+                        "hello world".to_string(),
+                        "it's".to_string(),
+                        "\"quoted\"".to_string(),
+                        "".to_string(),
+                        "back\\slash".to_string(),
+                    ],
+                    // written sorted by alias
+                    mapdir: vec![
+                        ("guest".to_string(), "C:\\Users\\me\\data".to_string()),
+                        ("hamlet".to_string(), "test_fs/hamlet".to_string()),
+                    ],
+                    tempdir: vec!["TEMP".to_string(), "SCRATCH".to_string()],
+                    pre_clean: vec!["out\\stale \"1\"\u{1b}.txt".to_string()],
+                    random_tempdir: vec!["TMP\\\"DIR\"\u{7}".to_string()],
+                    sparse_file: vec![SparseFile {
+                        path: "big\\\"file\"\u{1b}.bin".to_string(),
+                        offset: 1 << 30,
+                        marker: "end \u{1b}[0m".to_string(),
+                    }],
+                    assert_file_len: vec![("big\\\"file\"\u{1b}.bin".to_string(), 1 << 30)],
+                    ..WasiOptions::default()
+                },
+                ..WasiTest::default()
+            },
+            WasiTest {
+                wasm_prog_name: "bytes.wasm".to_string(),
+                stdout: "ok\n".to_string(),
+                options: WasiOptions {
+                    stdin_bytes: Some(vec![0, 0xff, b'\n', b'"', 0x80]),
+                    ..WasiOptions::default()
+                },
+                ..WasiTest::default()
+            },
+            WasiTest {
+                wasm_prog_name: "stdin.wasm".to_string(),
+                stderr: "read\n".to_string(),
+                options: WasiOptions {
+                    stdin: Some("line 1\nline \"2\"\n".to_string()),
+                    ..WasiOptions::default()
+                },
+                ..WasiTest::default()
+            },
+        ];
+        for test in tests {
+            let wast = test.into_wasi_wast();
+            assert_eq!(WasiTest::from_wast(&wast), Ok(test), "{}", wast);
+        }
+    }
+}
@@ -0,0 +1,9 @@
+#!/bin/sh
+# Builds the crates the tests link against with `// extern:`, for the native
+# build and the Wasm build of every WASI version, with their toolchains
+set -e
+cd "$(dirname "$0")"
+rustc --crate-type rlib --out-dir native greeting.rs
+rustc +nightly-2019-09-13 --target wasm32-wasi --crate-type rlib --out-dir unstable greeting.rs
+rustc +nightly-2019-12-18 --target wasm32-wasi --crate-type rlib --out-dir snapshot1 greeting.rs
+rustc +nightly-2024-11-01 --target wasm32-wasip2 --crate-type rlib --out-dir preview2 greeting.rs
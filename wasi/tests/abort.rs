@@ -0,0 +1,12 @@
+// WASI:
+// trap: unreachable
+
+// `abort` is a trap in Wasm, the output before it must still be flushed
+
+use std::io::Write;
+
+fn main() {
+    println!("about to abort");
+    std::io::stdout().flush().unwrap();
+    std::process::abort();
+}
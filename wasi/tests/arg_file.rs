@@ -0,0 +1,11 @@
+// WASI:
+// arg: --inline
+// arg_file: arg_file.txt
+
+// Prints every argument given, those of the file after the inline one
+
+fn main() {
+    for arg in std::env::args().skip(1) {
+        println!("{:?}", arg);
+    }
+}
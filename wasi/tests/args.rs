@@ -0,0 +1,12 @@
+// WASI:
+// arg: --name "hello world" ''
+// arg: 'single "quoted"' "double \"quoted\"" back\ slashed
+// arg: "" tab\	separated
+
+// Prints every argument given, so that quoting can be checked
+
+fn main() {
+    for arg in std::env::args().skip(1) {
+        println!("{:?}", arg);
+    }
+}
@@ -0,0 +1,11 @@
+// Writes bytes that aren't UTF-8 to stdout, which are asserted as bytes
+
+use std::io::Write;
+
+fn main() {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(&[0xff, 0xfe]).unwrap();
+    stdout.write_all(b" and text\n").unwrap();
+    stdout.flush().unwrap();
+}
@@ -0,0 +1,13 @@
+// WASI:
+// arg: first "second arg"
+
+#include <stdio.h>
+
+int main(int argc, char **argv) {
+    // argv[0] is the program's path natively but its name under WASI
+    for (int i = 1; i < argc; i++) {
+        printf("arg %d: %s\n", i, argv[i]);
+    }
+    fprintf(stderr, "%d args\n", argc - 1);
+    return 3;
+}
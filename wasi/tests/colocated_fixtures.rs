@@ -0,0 +1,16 @@
+// WASI:
+// mapdir: fixtures:colocated_fixtures
+// dirs_relative_to_test: true
+
+// The mapped directory is next to this file rather than in the directory the
+// tests run in, where the native program still runs.
+
+use std::fs;
+
+fn main() {
+    #[cfg(not(target_os = "wasi"))]
+    let path = "tests/colocated_fixtures/greeting.txt";
+    #[cfg(target_os = "wasi")]
+    let path = "fixtures/greeting.txt";
+    print!("{}", fs::read_to_string(path).unwrap());
+}
@@ -0,0 +1,17 @@
+// WASI:
+// cwd: test_fs/hamlet
+
+use std::fs;
+
+fn main() {
+    // relative to the `cwd`, without changing directory natively
+    let readme = fs::read_to_string("./README.md").unwrap();
+    println!("{}", readme.lines().next().unwrap());
+
+    let mut scenes = fs::read_dir("act1")
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    scenes.sort();
+    println!("{:?}", scenes);
+}
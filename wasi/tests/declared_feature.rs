@@ -0,0 +1,15 @@
+// WASI:
+// returns: 0
+
+// The feature is only declared for WASI, so that the test also builds with a
+// stable native toolchain. The generator mustn't declare it a second time.
+#![cfg_attr(target_os = "wasi", feature(wasi_ext))]
+
+#[cfg(not(target_os = "wasi"))]
+use std::os::unix::prelude::AsRawFd;
+#[cfg(target_os = "wasi")]
+use std::os::wasi::prelude::AsRawFd;
+
+fn main() {
+    println!("stdout is fd {}", std::io::stdout().as_raw_fd());
+}
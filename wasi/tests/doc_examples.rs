@@ -0,0 +1,35 @@
+//! The ```wasi examples below are also generated as their own tests when the
+//! generator is run with `--doctests`; the other code blocks are ignored.
+//!
+//! ```wasi
+//! // WASI:
+//! // env: GREETING=hello
+//!
+//! use std::env;
+//!
+//! fn main() {
+//!     #[cfg(not(target_os = "wasi"))]
+//!     env::set_var("GREETING", "hello");
+//!
+//!     println!("GREETING={}", env::var("GREETING").unwrap());
+//! }
+//! ```
+//!
+//! A helper that isn't a full program, and would fail to build as a test:
+//!
+//! ```rust
+//! fn shout(s: &str) -> String {
+//!     s.to_uppercase()
+//! }
+//! ```
+//!
+//! ```wasi
+//! fn main() {
+//!     eprintln!("exiting with 3");
+//!     std::process::exit(3);
+//! }
+//! ```
+
+fn main() {
+    println!("doc examples");
+}
@@ -0,0 +1,15 @@
+// WASI:
+// edition: 2018
+
+// in 2015, `use` paths start at the crate root rather than the current module
+mod greeting {
+    mod words {
+        pub const HELLO: &str = "hello";
+    }
+
+    pub use words::HELLO;
+}
+
+fn main() {
+    println!("{}", greeting::HELLO);
+}
@@ -0,0 +1,21 @@
+// WASI:
+// env: TOKEN=a=b=c
+// env: QUERY=?name=wasi&version=1
+
+// Only the first `=` of an `env` directive separates the name from the value
+
+fn get_env_var(var_name: &str) -> Option<String> {
+    #[cfg(not(target_os = "wasi"))]
+    match var_name {
+        "TOKEN" => Some("a=b=c".to_string()),
+        "QUERY" => Some("?name=wasi&version=1".to_string()),
+        _ => None,
+    }
+    #[cfg(target_os = "wasi")]
+    std::env::var(var_name).ok()
+}
+
+fn main() {
+    println!("TOKEN {:?}", get_env_var("TOKEN"));
+    println!("QUERY {:?}", get_env_var("QUERY"));
+}
@@ -0,0 +1,24 @@
+// WASI:
+// envfile: shared.env
+// env: SHARED=inline
+
+use std::env;
+
+fn get_env_var(var_name: &str) -> Result<String, env::VarError> {
+    #[cfg(not(target_os = "wasi"))]
+    match var_name {
+        "GREETING" => Ok("hello world".to_string()),
+        "EQUATION" => Ok("a=b".to_string()),
+        "SHARED" => Ok("inline".to_string()),
+        _ => Err(env::VarError::NotPresent),
+    }
+    #[cfg(target_os = "wasi")]
+    env::var(var_name)
+}
+
+fn main() {
+    println!("GREETING {:?}", get_env_var("GREETING"));
+    println!("EQUATION {:?}", get_env_var("EQUATION"));
+    println!("SHARED {:?}", get_env_var("SHARED"));
+    println!("MISSING {:?}", get_env_var("MISSING"));
+}
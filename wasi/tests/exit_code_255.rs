@@ -0,0 +1,12 @@
+// WASI:
+// assert_exit_matches_stdout: true
+
+use std::process;
+
+// the highest exit code that survives truncation to a byte
+const EXIT_CODE: i32 = 255;
+
+fn main() {
+    println!("{}", EXIT_CODE);
+    process::exit(EXIT_CODE);
+}
@@ -0,0 +1,13 @@
+// WASI:
+// exit: EXIT_USAGE=2
+
+use std::process;
+
+// the `exit` directive names the code in the `.wast`, and the native program
+// must exit with it too
+const EXIT_USAGE: i32 = 2;
+
+fn main() {
+    eprintln!("usage: exit_name");
+    process::exit(EXIT_USAGE);
+}
@@ -0,0 +1,8 @@
+// WASI:
+// extern: greeting=test_fs/externs/{target}/libgreeting.rlib
+
+extern crate greeting;
+
+fn main() {
+    println!("{}", greeting::greeting());
+}
@@ -0,0 +1,15 @@
+// WASI:
+// dir: test_fs
+// assert_file_len: test_fs/file_len.bin => 65536
+
+use std::fs::File;
+use std::io::Write;
+
+fn main() {
+    let mut file = File::create("test_fs/file_len.bin").unwrap();
+    let chunk = [0xAB; 1024];
+    for _ in 0..64 {
+        file.write_all(&chunk).unwrap();
+    }
+    println!("wrote 64 chunks of 1024 bytes");
+}
@@ -0,0 +1,23 @@
+// WASI:
+// dir: test_fs
+// record_fs_effects: test_fs/fs_effects
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+fn main() {
+    fs::write("test_fs/fs_effects/created.txt", "a new file\n").unwrap();
+
+    let mut to_modify = OpenOptions::new()
+        .append(true)
+        .open("test_fs/fs_effects/to_modify.txt")
+        .unwrap();
+    to_modify.write_all(b"second line\n").unwrap();
+
+    fs::remove_file("test_fs/fs_effects/to_delete.txt").unwrap();
+
+    fs::create_dir("test_fs/fs_effects/new_dir").unwrap();
+    fs::write("test_fs/fs_effects/new_dir/nested.txt", "nested\n").unwrap();
+
+    println!("created, modified and deleted files");
+}
@@ -0,0 +1,13 @@
+#!/usr/bin/env rustc
+//! The `// WASI:` header can follow a shebang, the crate's doc comment and
+//! its attributes.
+#![allow(unused_variables)]
+
+// WASI:
+// arg: after attributes
+
+fn main() {
+    let unused = ();
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    println!("{:?}", args);
+}
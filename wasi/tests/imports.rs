@@ -0,0 +1,10 @@
+// WASI:
+// imports: fd_write, proc_exit
+// imports: args_get, args_sizes_get, environ_get, environ_sizes_get
+
+// Only printing, so the Wasm module mustn't import a clock or `random_get`:
+// besides `fd_write`, the others are what `std` sets `main` up with
+
+fn main() {
+    println!("only fd_write");
+}
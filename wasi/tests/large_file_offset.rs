@@ -0,0 +1,18 @@
+// WASI:
+// dir: test_fs
+// sparse_file: test_fs/large.bin @ 5368709120 => past the 4GB mark
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+const MARKER_OFFSET: u64 = 5 * 1024 * 1024 * 1024;
+
+fn main() {
+    let mut file = File::open("test_fs/large.bin").unwrap();
+    let offset = file.seek(SeekFrom::Start(MARKER_OFFSET)).unwrap();
+    let mut marker = String::new();
+    file.read_to_string(&mut marker).unwrap();
+
+    println!("read {:?} at offset {}", marker, offset);
+    println!("file length: {}", file.metadata().unwrap().len());
+}
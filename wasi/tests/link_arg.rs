@@ -0,0 +1,17 @@
+// WASI:
+// link-arg: -lm
+
+// The linker argument is only for the native build: the program gets no
+// arguments
+extern "C" {
+    fn cbrt(x: f64) -> f64;
+}
+
+fn main() {
+    #[cfg(not(target_os = "wasi"))]
+    let cube_root = unsafe { cbrt(27.0) };
+    #[cfg(target_os = "wasi")]
+    let cube_root = 27.0f64.cbrt();
+    println!("{}", cube_root);
+    println!("{} arguments", std::env::args().skip(1).count());
+}
@@ -0,0 +1,10 @@
+// WASI:
+// assert_clock: monotonic
+
+use std::time::Instant;
+
+fn main() {
+    let before = Instant::now();
+    let after = Instant::now();
+    println!("monotonic clock went backwards: {}", after < before);
+}
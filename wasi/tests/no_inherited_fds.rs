@@ -0,0 +1,22 @@
+// WASI:
+// native_close_fds: 3
+
+use std::fs::File;
+use std::mem::ManuallyDrop;
+#[cfg(not(target_os = "wasi"))]
+use std::os::unix::io::FromRawFd;
+#[cfg(target_os = "wasi")]
+use std::os::wasi::io::FromRawFd;
+
+fn main() {
+    // Under WASI, the only fds past stdio are preopened directories. Natively,
+    // anything else here was inherited from the process that ran us.
+    let inherited = (3..64)
+        .filter(|&fd| {
+            // only look at the fd, closing it isn't ours to do
+            let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+            file.metadata().map(|m| !m.is_dir()).unwrap_or(false)
+        })
+        .count();
+    println!("non-directory fds: {}", inherited);
+}
@@ -0,0 +1,11 @@
+// WASI:
+// no-native-run
+
+// Only the Wasm program runs: natively, it would be looking for a device the
+// host doesn't have, so its output isn't asserted
+
+fn main() {
+    #[cfg(not(target_os = "wasi"))]
+    std::fs::read("/dev/wasi-test-device").expect("the device is there");
+    println!("running under WASI");
+}
@@ -0,0 +1,24 @@
+// WASI:
+// overflow_checks: true
+
+use std::env;
+use std::panic;
+use std::process;
+
+fn main() {
+    // panics abort under WASI, so report the panic and exit the same way natively
+    panic::set_hook(Box::new(|info| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .unwrap_or("unknown panic");
+        println!("panicked: {}", message);
+        process::exit(101);
+    }));
+
+    // computed at runtime so the overflow isn't caught at compile time
+    let max = 254 + env::args().count() as u8;
+    let overflowed = max + 1;
+    println!("no overflow check: {}", overflowed);
+}
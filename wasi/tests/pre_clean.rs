@@ -0,0 +1,17 @@
+// WASI:
+// dir: test_fs
+// pre_clean: test_fs/pre_clean
+
+use std::fs;
+
+fn main() {
+    // both fail if a previous run left its output behind
+    fs::create_dir("test_fs/pre_clean").unwrap();
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open("test_fs/pre_clean/output.txt")
+        .unwrap();
+
+    println!("started from a clean state");
+}
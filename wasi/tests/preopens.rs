@@ -0,0 +1,47 @@
+// WASI:
+// dir: test_fs
+// mapdir: hamlet:test_fs/hamlet
+// assert_preopens: 4
+
+#[cfg(target_os = "wasi")]
+#[repr(C)]
+struct Prestat {
+    tag: u8,
+    dir_name_len: usize,
+}
+
+#[cfg(target_os = "wasi")]
+#[link(wasm_import_module = "wasi_unstable")]
+extern "C" {
+    fn fd_prestat_get(fd: u32, buf: *mut Prestat) -> u16;
+    fn fd_prestat_dir_name(fd: u32, path: *mut u8, path_len: usize) -> u16;
+}
+
+const FIRST_PREOPEN_FD: u32 = 4;
+
+fn main() {
+    #[cfg(target_os = "wasi")]
+    {
+        let mut fd = FIRST_PREOPEN_FD;
+        loop {
+            let mut prestat = Prestat {
+                tag: 0,
+                dir_name_len: 0,
+            };
+            if unsafe { fd_prestat_get(fd, &mut prestat) } != 0 {
+                break;
+            }
+            let mut name = vec![0; prestat.dir_name_len];
+            let result = unsafe { fd_prestat_dir_name(fd, name.as_mut_ptr(), name.len()) };
+            assert_eq!(result, 0, "fd_prestat_dir_name failed for fd {}", fd);
+            println!("{}: {}", fd, String::from_utf8_lossy(&name));
+            fd += 1;
+        }
+    }
+
+    #[cfg(not(target_os = "wasi"))]
+    {
+        println!("{}: test_fs", FIRST_PREOPEN_FD);
+        println!("{}: hamlet", FIRST_PREOPEN_FD + 1);
+    }
+}
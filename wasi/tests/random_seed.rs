@@ -0,0 +1,21 @@
+// WASI:
+// seed: 42
+
+use std::env;
+
+// The output only depends on the seed: the program seeds its own generator
+// with it rather than using `random_get`, which the runner may not seed
+
+fn main() {
+    let mut state: u64 = env::var("WASI_TEST_RANDOM_SEED")
+        .expect("the seed is given")
+        .parse()
+        .expect("the seed is a number");
+    for _ in 0..3 {
+        // the LCG of Knuth's MMIX
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        println!("{}", state >> 33);
+    }
+}
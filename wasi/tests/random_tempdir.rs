@@ -0,0 +1,15 @@
+// WASI:
+// random_tempdir: SCRATCH_DIR
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let scratch_dir = env::var("SCRATCH_DIR").unwrap();
+    let path = Path::new(&scratch_dir).join("created.txt");
+    fs::write(&path, "in a temp dir with a random name\n").unwrap();
+
+    println!("created {}", path.display());
+    print!("{}", fs::read_to_string(&path).unwrap());
+}
@@ -0,0 +1,20 @@
+// WASI:
+// mapdir: hamlet:test_fs/hamlet
+// read_only: true
+
+use std::fs;
+
+fn main() {
+    #[cfg(not(target_os = "wasi"))]
+    let path = "test_fs/hamlet/act6.txt";
+    #[cfg(target_os = "wasi")]
+    let path = "hamlet/act6.txt";
+
+    match fs::write(path, "Enter a sixth act.\n") {
+        Ok(()) => {
+            println!("writing to a read-only preopen succeeded");
+            fs::remove_file(path).unwrap();
+        }
+        Err(_) => println!("writing to a read-only preopen failed"),
+    }
+}
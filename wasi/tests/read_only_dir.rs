@@ -0,0 +1,18 @@
+// WASI:
+// dir: test_fs/hamlet:ro
+
+use std::fs;
+
+fn main() {
+    let path = "test_fs/hamlet/act6.txt";
+
+    match fs::write(path, "Enter a sixth act.\n") {
+        Ok(()) => {
+            println!("writing to a read-only dir succeeded");
+            fs::remove_file(path).unwrap();
+        }
+        Err(_) => println!("writing to a read-only dir failed"),
+    }
+    let readme = fs::read_to_string("test_fs/hamlet/README.md").unwrap();
+    println!("reading from it works: {} bytes", readme.len());
+}
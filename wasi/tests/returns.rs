@@ -0,0 +1,12 @@
+// WASI:
+// dir: test_fs
+// pre_clean: test_fs/created_by_returns.txt
+// returns: 0
+// returns: exists test_fs/created_by_returns.txt
+
+use std::fs;
+
+fn main() {
+    fs::write("test_fs/created_by_returns.txt", "created\n").unwrap();
+    println!("wrote test_fs/created_by_returns.txt");
+}
@@ -0,0 +1,13 @@
+// WASI:
+// scrub: [0-9a-f]{16}
+
+// The token is random, so it's asserted as `token [scrubbed]`
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+fn main() {
+    let token = RandomState::new().build_hasher().finish();
+    println!("token {:016x}", token);
+    println!("done");
+}
@@ -0,0 +1,14 @@
+// WASI:
+// trap: out of bounds memory access
+
+// a segfault is a trap in Wasm, the output before it must still be flushed
+
+use std::io::Write;
+
+fn main() {
+    println!("about to read out of bounds");
+    std::io::stdout().flush().unwrap();
+    // unmapped natively, and past the end of the linear memory in Wasm
+    let byte = unsafe { std::ptr::read_volatile((usize::MAX - 15) as *const u8) };
+    println!("read {}", byte);
+}
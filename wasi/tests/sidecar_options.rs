@@ -0,0 +1,17 @@
+// WASI:
+// env: GREETING=from the header
+// env: NAME=world
+// arg: header
+
+// `sidecar_options.wasi.toml` replaces the `env` and `args` of the header.
+
+use std::env;
+
+fn main() {
+    let greeting = env::var("GREETING").unwrap_or_default();
+    let name = env::var("NAME").unwrap_or_else(|_| "nobody".to_string());
+    println!("{}, {}!", greeting, name);
+    for arg in env::args().skip(1) {
+        println!("arg: {}", arg);
+    }
+}
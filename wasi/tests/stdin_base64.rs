@@ -0,0 +1,19 @@
+// WASI:
+// stdin_base64: iVdUAgAFaGVsbG8=
+
+// A binary header: a magic number, a version, and the length of the
+// payload as a big-endian u16
+
+use std::io::{self, Read};
+
+const MAGIC: &[u8] = b"\x89WT";
+
+fn main() {
+    let mut input = vec![];
+    io::stdin().read_to_end(&mut input).unwrap();
+    assert_eq!(&input[..3], MAGIC, "bad magic number");
+    let version = input[3];
+    let len = u16::from_be_bytes([input[4], input[5]]) as usize;
+    let payload = std::str::from_utf8(&input[6..6 + len]).unwrap();
+    println!("version {}: {}", version, payload);
+}
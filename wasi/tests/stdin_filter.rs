@@ -0,0 +1,13 @@
+// WASI:
+// stdin: "first line\nsecond\t\"quoted\" line\n\nlast line without newline"
+
+// Uppercases stdin line by line, like a filter in a pipeline
+
+use std::io::{self, BufRead};
+
+fn main() {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        println!("{}", line.unwrap().to_uppercase());
+    }
+}
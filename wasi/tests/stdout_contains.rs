@@ -0,0 +1,17 @@
+// WASI:
+// stdout_contains: "started at "
+// stdout_contains: done
+
+// The time varies from run to run, so only what's around it is asserted,
+// while stderr is asserted whole
+
+use std::time::SystemTime;
+
+fn main() {
+    let started = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    println!("started at {}", started.as_secs());
+    println!("done");
+    eprintln!("no errors");
+}
@@ -0,0 +1,11 @@
+// WASI:
+// tempdir: scratch
+// assert_tempdir_file: scratch/greeting.txt
+// assert_tempdir_file: scratch/bytes.bin
+
+use std::fs;
+
+fn main() {
+    fs::write("scratch/greeting.txt", "hello\n").unwrap();
+    fs::write("scratch/bytes.bin", [0xff, 0x00, 0x7f]).unwrap();
+}
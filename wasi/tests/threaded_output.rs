@@ -0,0 +1,26 @@
+// WASI:
+// serialize_output: true
+
+const WORKERS: usize = 8;
+
+fn work(id: usize) {
+    println!("worker {} computed {}", id, (1..=id * 1000).sum::<usize>());
+}
+
+fn main() {
+    #[cfg(not(target_os = "wasi"))]
+    {
+        let handles = (0..WORKERS)
+            .map(|id| std::thread::spawn(move || work(id)))
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    // there are no threads here, the workers run one after the other
+    #[cfg(target_os = "wasi")]
+    for id in (0..WORKERS).rev() {
+        work(id);
+    }
+}
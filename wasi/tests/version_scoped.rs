@@ -0,0 +1,13 @@
+// WASI:
+// dir: test_fs/hamlet
+// WASI(snapshot1): mapdir: act1::test_fs/hamlet/act1
+// WASI(unstable): env: WASI_VERSION=unstable
+
+// The scoped directives only go to the `.wast` of their version, merged with
+// the others: the `snapshot1` one has both preopens, the `unstable` one the
+// env var. The native program runs with the unscoped ones, so the output is
+// the same for both.
+
+fn main() {
+    println!("the same output for every version");
+}
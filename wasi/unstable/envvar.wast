@@ -1,7 +1,7 @@
 ;; This file was generated by https://github.com/wasmerio/wasi-tests
 
 (wasi_test "envvar.wasm"
-  (envs "DOG=1" "CAT=2")
+  (envs "CAT=2" "DOG=1")
   (assert_return (i64.const 0))
   (assert_stdout "Env vars:\nCAT=2\nDOG=1\nDOG Ok(\"1\")\nDOG_TYPE Err(NotPresent)\nSET VAR Ok(\"HELLO\")\n")
 )
@@ -1,7 +1,7 @@
 ;; This file was generated by https://github.com/wasmerio/wasi-tests
 
 (wasi_test "writing.wasm"
-  (map_dirs "act1:test_fs/hamlet/act1" "act2:test_fs/hamlet/act2" "act1-again:test_fs/hamlet/act1")
+  (map_dirs "act1:test_fs/hamlet/act1" "act1-again:test_fs/hamlet/act1" "act2:test_fs/hamlet/act2")
   (assert_return (i64.const 0))
   (assert_stdout "abcdefghijklmnopqrstuvwxyz\nfile is gone\n")
 )